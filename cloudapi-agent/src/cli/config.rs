@@ -0,0 +1,164 @@
+use anyhow::Result;
+use reqwest::Url;
+use std::path::Path;
+use tracing_subscriber::filter::LevelFilter;
+
+use crate::config::AgentConfig;
+use crate::constants;
+
+fn default_config_path() -> std::path::PathBuf {
+    Path::new(constants::DEFAULT_CLOUD_API_ROOT_DIR).join(constants::AGENT_CONFIG_FILE)
+}
+
+pub fn show_config() -> Result<()> {
+    let mut config = AgentConfig::load(&default_config_path())?;
+
+    config.cloudapi_endpoints = config.get_cloudapi_endpoints();
+    config.cloudapi_endpoint = None;
+
+    if config.registration_secret.is_some() {
+        config.registration_secret = Some("<redacted>".to_string());
+    }
+
+    println!("{}", serde_json::to_string_pretty(&config)?);
+
+    Ok(())
+}
+
+pub fn validate_config(path: Option<&Path>) -> Result<()> {
+    let path = path.map(Path::to_path_buf).unwrap_or_else(default_config_path);
+    let config = AgentConfig::load(&path)?;
+    let errors = config_errors(&config);
+
+    if errors.is_empty() {
+        println!("{} is valid.", path.to_string_lossy());
+        return Ok(());
+    }
+
+    for error in &errors {
+        println!("error: {}", error);
+    }
+
+    Err(anyhow::anyhow!("{} has {} error(s)", path.to_string_lossy(), errors.len()))
+}
+
+fn config_errors(config: &AgentConfig) -> Vec<String> {
+    let mut errors = vec![];
+
+    for endpoint in config.get_cloudapi_endpoints() {
+        match Url::parse(&endpoint) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+            Ok(url) => errors.push(format!("endpoint {} has unsupported scheme {}", endpoint, url.scheme())),
+            Err(e) => errors.push(format!("endpoint {} is not a valid URL: {}", endpoint, e)),
+        }
+    }
+
+    if config.package_cache.trim().is_empty() {
+        errors.push("package_cache must not be empty".to_string());
+    }
+
+    let poll = config.get_poll_config();
+
+    if poll.interval_secs == 0 {
+        errors.push("poll.interval_secs must be greater than 0".to_string());
+    }
+
+    if poll.max_backoff_secs < poll.interval_secs {
+        errors.push("poll.max_backoff_secs must not be less than poll.interval_secs".to_string());
+    }
+
+    if config.heartbeat.enabled && config.heartbeat.interval_secs == 0 {
+        errors.push("heartbeat.interval_secs must be greater than 0".to_string());
+    }
+
+    if config.user_data.enabled && config.user_data.timeout_secs == 0 {
+        errors.push("user_data.timeout_secs must be greater than 0".to_string());
+    }
+
+    if config.hooks.max_captured_bytes == 0 {
+        errors.push("hooks.max_captured_bytes must be greater than 0".to_string());
+    }
+
+    if config.hooks.max_log_file_bytes == 0 {
+        errors.push("hooks.max_log_file_bytes must be greater than 0".to_string());
+    }
+
+    if config.logging.level.parse::<LevelFilter>().is_err() {
+        errors.push(format!("logging.level {} is not a valid level", config.logging.level));
+    }
+
+    for (module, level) in &config.logging.modules {
+        if level.parse::<LevelFilter>().is_err() {
+            errors.push(format!("logging.modules.{} level {} is not a valid level", module, level));
+        }
+    }
+
+    if config.logging.file.enabled && config.logging.file.max_files == 0 {
+        errors.push("logging.file.max_files must be greater than 0".to_string());
+    }
+
+    if config.metrics.enabled && config.metrics.listen_address.parse::<std::net::SocketAddr>().is_err() {
+        errors.push(format!("metrics.listen_address {} is not a valid socket address", config.metrics.listen_address));
+    }
+
+    if let Some(resource) = &config.resource {
+        if resource.namespace.is_empty() || resource.name.is_empty() {
+            errors.push("resource must have a namespace and a name".to_string());
+        }
+    }
+
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cloudapi_sdk::model::resource::ResourceRef;
+
+    #[test]
+    fn the_default_config_is_valid() {
+        assert!(config_errors(&AgentConfig::default()).is_empty(), "{:?}", config_errors(&AgentConfig::default()));
+    }
+
+    #[test]
+    fn reports_every_invalid_setting() {
+        let mut config = AgentConfig {
+            cloudapi_endpoints: vec!["ftp://example".to_string(), "not a url".to_string()],
+            package_cache: " ".to_string(),
+            resource: Some(ResourceRef::new("", "vm-1")),
+            ..AgentConfig::default()
+        };
+        config.poll.interval_secs = 0;
+        config.hooks.max_captured_bytes = 0;
+        config.logging.level = "verbose".to_string();
+        config.logging.modules.insert("cloudapi_agent::service".to_string(), "loud".to_string());
+        config.metrics.enabled = true;
+        config.metrics.listen_address = "localhost".to_string();
+
+        let errors = config_errors(&config);
+
+        for expected in [
+            "endpoint ftp://example has unsupported scheme ftp",
+            "endpoint not a url is not a valid URL",
+            "package_cache must not be empty",
+            "poll.interval_secs must be greater than 0",
+            "hooks.max_captured_bytes must be greater than 0",
+            "logging.level verbose is not a valid level",
+            "logging.modules.cloudapi_agent::service level loud is not a valid level",
+            "metrics.listen_address localhost is not a valid socket address",
+            "resource must have a namespace and a name",
+        ] {
+            assert!(errors.iter().any(|error| error.starts_with(expected)), "missing {:?} in {:?}", expected, errors);
+        }
+
+        assert_eq!(errors.len(), 9, "{:?}", errors);
+    }
+
+    #[test]
+    fn backoff_must_not_be_shorter_than_the_interval() {
+        let mut config = AgentConfig::default();
+        config.poll.max_backoff_secs = config.poll.interval_secs - 1;
+
+        assert_eq!(config_errors(&config), vec!["poll.max_backoff_secs must not be less than poll.interval_secs"]);
+    }
+}
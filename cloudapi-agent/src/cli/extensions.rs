@@ -0,0 +1,191 @@
+use anyhow::{Context, Result};
+use chrono::SecondsFormat;
+use cloudapi_sdk::model::extension::ExtensionStatus;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::control::{self, ControlCommand};
+use crate::extension::find_history_package_id;
+use crate::extension::history::{self, HookRun};
+use crate::state::{DesiredState, StateDb};
+
+pub fn list_extensions() -> Result<()> {
+    let desired_state = DesiredState::load(&DesiredState::default_path())?;
+    let state_db = StateDb::load(&StateDb::default_path())?;
+
+    println!("{:<40} {:<12} {:<14} {:<26} LAST ERROR", "PACKAGE", "VERSION", "STATUS", "UPDATED");
+
+    for desired in desired_state.get_extensions() {
+        match state_db.get(&desired.uid) {
+            Some(observed) => println!(
+                "{:<40} {:<12} {:<14} {:<26} {}",
+                observed.package_id,
+                observed.version,
+                format!("{:?}", observed.status),
+                observed.updated_at,
+                observed.last_error.as_deref().unwrap_or("")
+            ),
+            None => println!(
+                "{:<40} {:<12} {:<14} {:<26}",
+                desired.get_package_id(),
+                desired.version,
+                format!("{:?}", ExtensionStatus::NotInstalled),
+                "-"
+            ),
+        }
+    }
+
+    // Extensions that are still tracked but no longer assigned are being removed.
+    for observed in state_db.extensions.values() {
+        if !desired_state.get_extensions().iter().any(|desired| desired.uid == observed.uid) {
+            println!(
+                "{:<40} {:<12} {:<14} {:<26} {}",
+                observed.package_id,
+                observed.version,
+                format!("{:?} (unassigned)", observed.status),
+                observed.updated_at,
+                observed.last_error.as_deref().unwrap_or("")
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * Prints the output of an extension's most recent hook run, asking the running agent
+ * first and reading the run history from disk when the agent is not reachable.
+ */
+pub async fn show_logs(id: &str) -> Result<()> {
+    let run: HookRun = match control::send_command(ControlCommand::ExtensionLogs { id: id.to_string() }).await {
+        Ok(result) => serde_json::from_value(result)?,
+        Err(_) => {
+            let package_id = find_package_id(id)?;
+            history::last_run(&package_id)?
+                .context(format!("Extension {} has not run any hooks", package_id))?
+        }
+    };
+
+    print_run(&run);
+
+    Ok(())
+}
+
+/**
+ * Prints the log file of an extension's latest hook run as it grows, until the run is
+ * recorded in the history. Reads the files directly, so it works whether or not the
+ * agent is running.
+ */
+pub async fn follow_logs(id: &str) -> Result<()> {
+    let package_id = find_package_id(id)?;
+    let path = history::latest_log_file(&package_id)?
+        .context(format!("Extension {} has no hook run logs", package_id))?;
+
+    let mut file = File::open(&path)
+        .context(format!("Failed to open run log: {}", path.to_string_lossy()))?;
+    let mut position = 0;
+
+    loop {
+        // Check before reading so the output written before the run finished is not missed.
+        let finished = history::last_run(&package_id)?
+            .filter(|run| run.log_file.as_deref() == Some(path.as_path()));
+
+        position = copy_appended(&mut file, position, &path)?;
+
+        if let Some(run) = finished {
+            println!(
+                "--- {} hook finished after {}ms, exit code {}{} ---",
+                run.hook.as_str(),
+                run.duration_ms,
+                run.exit_code.map(|code| code.to_string()).unwrap_or_else(|| "-".to_string()),
+                run.error.as_ref().map(|error| format!(": {}", error)).unwrap_or_default()
+            );
+            return Ok(());
+        }
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+}
+
+/**
+ * Writes what was appended to the file since `position` to stdout and returns the new position.
+ */
+fn copy_appended(file: &mut File, position: u64, path: &Path) -> Result<u64> {
+    let mut appended = Vec::new();
+
+    file.seek(SeekFrom::Start(position))
+        .and_then(|_| file.read_to_end(&mut appended))
+        .context(format!("Failed to read run log: {}", path.to_string_lossy()))?;
+
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(&appended)?;
+    stdout.flush()?;
+
+    Ok(position + appended.len() as u64)
+}
+
+pub async fn show_history(id: &str, limit: usize) -> Result<()> {
+    let runs: Vec<HookRun> = match control::send_command(ControlCommand::ExtensionHistory { id: id.to_string(), limit: Some(limit) }).await {
+        Ok(result) => serde_json::from_value(result)?,
+        Err(_) => history::read_history(&find_package_id(id)?, Some(limit))?,
+    };
+
+    println!("{:<22} {:<14} {:<12} {:<16} {:>10} {:<6} ERROR", "STARTED", "HOOK", "VERSION", "TRIGGER", "DURATION", "EXIT");
+
+    for run in &runs {
+        println!(
+            "{:<22} {:<14} {:<12} {:<16} {:>10} {:<6} {}",
+            run.started_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            run.hook.as_str(),
+            run.version,
+            format!("{:?}", run.trigger),
+            format!("{}ms", run.duration_ms),
+            run.exit_code.map(|code| code.to_string()).unwrap_or_else(|| "-".to_string()),
+            run.error.as_deref().unwrap_or("")
+        );
+    }
+
+    Ok(())
+}
+
+pub async fn rerun_hook(id: &str) -> Result<()> {
+    let result = control::send_command(ControlCommand::RerunHook { id: id.to_string() }).await?;
+    let run: HookRun = serde_json::from_value(result)?;
+
+    print_run(&run);
+
+    Ok(())
+}
+
+fn find_package_id(id: &str) -> Result<String> {
+    let desired_state = DesiredState::load(&DesiredState::default_path())?;
+    let state_db = StateDb::load(&StateDb::default_path())?;
+
+    find_history_package_id(&desired_state, &state_db, id)
+        .context(format!("Extension {} is neither assigned, tracked nor in the run history", id))
+}
+
+fn print_run(run: &HookRun) {
+    println!(
+        "{} {} {} hook ({:?}{}), started {}, took {}ms, exit code {}",
+        run.package_id,
+        run.version,
+        run.hook.as_str(),
+        run.trigger,
+        run.detail.as_ref().map(|detail| format!(": {}", detail)).unwrap_or_default(),
+        run.started_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+        run.duration_ms,
+        run.exit_code.map(|code| code.to_string()).unwrap_or_else(|| "-".to_string())
+    );
+
+    if let Some(error) = &run.error {
+        println!("error: {}", error);
+    }
+
+    println!("--- stdout{} ---", if run.stdout_truncated { " (truncated)" } else { "" });
+    println!("{}", run.stdout.trim_end());
+    println!("--- stderr{} ---", if run.stderr_truncated { " (truncated)" } else { "" });
+    println!("{}", run.stderr.trim_end());
+}
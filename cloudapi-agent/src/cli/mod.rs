@@ -0,0 +1,142 @@
+mod config;
+mod extensions;
+mod plan;
+mod status;
+
+use anyhow::Result;
+use crate::control::{self, ControlCommand};
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+/**
+ * Command line of the agent. Without a subcommand the agent runs as a service.
+ */
+#[derive(Debug, Parser)]
+#[command(name = "cloudapi-agent", version, about = "Cloud API guest agent")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the agent service (the default).
+    Run,
+    /// Set up the data directory and config, and register the agent as a system service.
+    Install(InstallArgs),
+    /// Remove the system service and the installed agent binary.
+    Uninstall(UninstallArgs),
+    /// Show what the agent is doing.
+    Status,
+    /// Inspect extensions on this machine.
+    #[command(subcommand)]
+    Extensions(ExtensionsCommand),
+    /// Reconcile extensions against the server.
+    Reconcile(ReconcileArgs),
+    /// Inspect the agent config.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ExtensionsCommand {
+    /// List assigned and tracked extensions with their observed state.
+    List,
+    /// Print the output of an extension's most recent hook run.
+    Logs {
+        /// Extension id, package id or uid.
+        id: String,
+        /// Tail the log file of the latest run until the run finishes.
+        #[arg(long, short)]
+        follow: bool,
+    },
+    /// List an extension's hook runs, newest first.
+    History {
+        /// Extension id, package id or uid.
+        id: String,
+        /// Show at most this many runs.
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Ask the running agent to re-run an extension's install script.
+    Rerun {
+        /// Extension id, package id or uid.
+        id: String,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct ReconcileArgs {
+    /// Run a single poll and reconciliation in this process, then exit, instead of
+    /// asking the running agent to reconcile now.
+    #[arg(long)]
+    pub once: bool,
+    /// Print what a reconciliation would do without downloading or executing anything.
+    #[arg(long, conflicts_with = "once")]
+    pub plan: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Print the effective agent config.
+    Show,
+    /// Check the agent config for errors.
+    Validate {
+        /// Config file to validate instead of the installed one.
+        #[arg(long, value_name = "PATH")]
+        path: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct InstallArgs {
+    /// Metadata endpoint to use; repeat for failover endpoints in order.
+    #[arg(long = "endpoint", value_name = "URL")]
+    pub endpoints: Vec<String>,
+    /// Directory downloaded extension packages are cached in.
+    #[arg(long, value_name = "PATH")]
+    pub package_cache: Option<String>,
+    /// Secret presented to the server when registering.
+    #[arg(long)]
+    pub registration_secret: Option<String>,
+    /// Overwrite an existing agent config.
+    #[arg(long)]
+    pub force: bool,
+    /// Only set up files; do not register or start the system service.
+    #[arg(long)]
+    pub no_service: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct UninstallArgs {
+    /// Also uninstall tracked extensions and delete the data directory.
+    #[arg(long)]
+    pub purge: bool,
+}
+
+/**
+ * Runs every subcommand other than `run`, which starts the service.
+ */
+pub async fn run_command(command: &Command) -> Result<()> {
+    match command {
+        Command::Run => return Err(anyhow::anyhow!("`run` starts the service and is not a one-off command")),
+        Command::Install(args) => crate::installer::install(args).await?,
+        Command::Uninstall(args) => crate::installer::uninstall(args).await?,
+        Command::Status => status::show_status().await?,
+        Command::Extensions(ExtensionsCommand::List) => extensions::list_extensions()?,
+        Command::Extensions(ExtensionsCommand::Logs { id, follow: false }) => extensions::show_logs(id).await?,
+        Command::Extensions(ExtensionsCommand::Logs { id, follow: true }) => extensions::follow_logs(id).await?,
+        Command::Extensions(ExtensionsCommand::History { id, limit }) => extensions::show_history(id, *limit).await?,
+        Command::Extensions(ExtensionsCommand::Rerun { id }) => extensions::rerun_hook(id).await?,
+        Command::Reconcile(args) if args.plan => plan::show_plan().await?,
+        Command::Reconcile(args) if args.once => crate::service::reconcile_once().await?,
+        Command::Reconcile(_) => {
+            control::send_command(ControlCommand::Reconcile).await?;
+            println!("Reconciliation requested.");
+        }
+        Command::Config(ConfigCommand::Show) => config::show_config()?,
+        Command::Config(ConfigCommand::Validate { path }) => config::validate_config(path.as_deref())?,
+    }
+
+    Ok(())
+}
@@ -0,0 +1,33 @@
+use anyhow::Result;
+
+use crate::service::plan::ReconcilePlan;
+
+/**
+ * Computes the plan in this process against the latest assignments from the server.
+ */
+pub async fn show_plan() -> Result<()> {
+    print_plan(&crate::service::plan_once().await?);
+
+    Ok(())
+}
+
+fn print_plan(plan: &ReconcilePlan) {
+    println!("Desired state fetched: {}", plan.desired_state_fetched_at.as_deref().unwrap_or("never"));
+
+    if plan.steps.is_empty() {
+        println!("No changes.");
+        return;
+    }
+
+    println!("{:<14} {:<40} {:<12} REASON", "ACTION", "PACKAGE", "VERSION");
+
+    for step in &plan.steps {
+        println!(
+            "{:<14} {:<40} {:<12} {}",
+            format!("{:?}", step.action),
+            step.package_id,
+            step.version.as_deref().unwrap_or("-"),
+            step.reason
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use cloudapi_sdk::model::extension::ExtensionStatus;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::config::AgentConfig;
+use crate::constants;
+use crate::control::{self, ControlCommand};
+use crate::state::{DesiredState, RuntimeStatus, StateDb};
+
+/**
+ * Prints the agent's status, taking the live status from the running agent when it is
+ * reachable and the last status it wrote to disk otherwise.
+ */
+pub async fn show_status() -> Result<()> {
+    let config_file = Path::new(constants::DEFAULT_CLOUD_API_ROOT_DIR).join(constants::AGENT_CONFIG_FILE);
+    let config = AgentConfig::load(&config_file).ok();
+
+    println!("Config:          {}{}", config_file.to_string_lossy(), if config.is_some() { "" } else { " (missing or invalid)" });
+
+    let live_status = control::send_command(ControlCommand::Status).await.ok()
+        .and_then(|result| serde_json::from_value::<Option<RuntimeStatus>>(result).ok());
+    let connected = live_status.is_some();
+    let runtime_status = match live_status {
+        Some(runtime_status) => runtime_status,
+        None => RuntimeStatus::load(&RuntimeStatus::default_path())?,
+    };
+
+    match runtime_status {
+        Some(runtime) => {
+            let stale_after = config.as_ref()
+                .map(|config| config.get_poll_config().max_backoff_secs + config.get_poll_config().jitter_secs)
+                .unwrap_or(300) * 2;
+            let age = DateTime::parse_from_rfc3339(&runtime.updated_at).ok()
+                .map(|updated_at| (Utc::now() - updated_at.with_timezone(&Utc)).num_seconds());
+            let state = match age {
+                _ if connected => "running",
+                Some(age) if age <= stale_after as i64 => "running (control API unavailable)",
+                _ => "not reporting",
+            };
+
+            println!("Agent:           {} (pid {}, version {})", state, runtime.pid, runtime.agent_version);
+            println!("Started:         {}", runtime.started_at);
+            println!("Last poll:       {} ({})", runtime.updated_at, runtime.last_outcome.as_deref().unwrap_or("unknown"));
+            println!("Next poll:       {}", runtime.next_poll_at.as_deref().unwrap_or("unknown"));
+            println!("Endpoint:        {}", runtime.active_endpoint.as_deref().unwrap_or("none"));
+        }
+        None => println!("Agent:           has not run yet"),
+    }
+
+    let desired_state = DesiredState::load(&DesiredState::default_path())?;
+    println!("Desired state:   {} extension(s), fetched {}", desired_state.get_extensions().len(), desired_state.fetched_at.as_deref().unwrap_or("never"));
+
+    let state_db = StateDb::load(&StateDb::default_path())?;
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+
+    for observed in state_db.extensions.values() {
+        *counts.entry(format!("{:?}", observed.status)).or_default() += 1;
+    }
+
+    let summary: Vec<String> = counts.iter().map(|(status, count)| format!("{} {}", count, status)).collect();
+    println!("Extensions:      {}", if summary.is_empty() { "none tracked".to_string() } else { summary.join(", ") });
+
+    let failed = state_db.extensions.values().filter(|observed| observed.status == ExtensionStatus::Failed).count();
+
+    if failed > 0 {
+        println!();
+        println!("{} extension(s) failed; see `cloudapi-agent extensions list`.", failed);
+    }
+
+    Ok(())
+}
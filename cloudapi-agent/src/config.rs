@@ -1,50 +1,327 @@
 
-use cloudapi_sdk::model::extension::ExtensionState;
+use anyhow::{Context, Result};
+use cloudapi_sdk::model::auth::RegistrationRequest;
+use cloudapi_sdk::model::resource::ResourceRef;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
 
 use crate::constants;
 
+/**
+ * Operator-owned agent settings. The agent only ever reads this file; desired and
+ * observed extension state live in `desired-state.json` and `state.json`.
+ */
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AgentConfig {
-    pub cloudapi_endpoint: String,
+    /// Ordered list of metadata endpoints; later entries are used when earlier ones are unreachable.
+    #[serde(default)]
+    pub cloudapi_endpoints: Vec<String>,
+    /// Single endpoint used by configs written before `cloudapi_endpoints` existed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cloudapi_endpoint: Option<String>,
     pub package_cache: String,
-    pub extensions: Vec<ExtensionState>,
+    #[serde(default)]
+    pub poll: PollConfig,
+    /// The VirtualMachine resource this agent registers as. When unset the server resolves
+    /// it from the agent's source address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resource: Option<ResourceRef>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registration_secret: Option<String>,
+    #[serde(default)]
+    pub user_data: UserDataConfig,
+    #[serde(default)]
+    pub heartbeat: HeartbeatConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub hooks: HookConfig,
+}
+
+/**
+ * Controls how often the agent polls the endpoint. Failed polls back off exponentially
+ * from `interval_secs` up to `max_backoff_secs`; every delay gets up to `jitter_secs` of
+ * random jitter so a fleet does not poll in lockstep.
+ */
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct PollConfig {
+    pub interval_secs: u64,
+    pub jitter_secs: u64,
+    pub max_backoff_secs: u64,
+    pub pending_work_interval_secs: u64,
+}
+
+impl Default for PollConfig {
+    fn default() -> Self {
+        PollConfig {
+            interval_secs: 15,
+            jitter_secs: 5,
+            max_backoff_secs: 300,
+            pending_work_interval_secs: 2,
+        }
+    }
+}
+
+/**
+ * Controls first-boot execution of the user-data assigned to this machine.
+ */
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct UserDataConfig {
+    pub enabled: bool,
+    pub timeout_secs: u64,
+}
+
+impl Default for UserDataConfig {
+    fn default() -> Self {
+        UserDataConfig {
+            enabled: true,
+            timeout_secs: 1800,
+        }
+    }
+}
+
+/**
+ * Controls the liveness heartbeat sent to the server. Heartbeats are sent on their own
+ * schedule, independent of polling and reconciliation.
+ */
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct HeartbeatConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        HeartbeatConfig {
+            enabled: true,
+            interval_secs: 30,
+        }
+    }
+}
+
+/**
+ * Limits on the output of extension hooks. Output is streamed line by line into the log
+ * and a log file per run while the hook runs; the end of it is also kept in the run
+ * history, up to `max_captured_bytes` per stream.
+ */
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct HookConfig {
+    pub max_captured_bytes: usize,
+    /// Output past this size is not written to the run's log file.
+    pub max_log_file_bytes: u64,
+}
+
+impl Default for HookConfig {
+    fn default() -> Self {
+        HookConfig {
+            max_captured_bytes: 64 * 1024,
+            max_log_file_bytes: 16 * 1024 * 1024,
+        }
+    }
+}
+
+impl HookConfig {
+    /**
+     * The hook settings of the installed agent config, for callers outside the poll loop.
+     */
+    pub fn load_or_default() -> Self {
+        let path = Path::new(constants::DEFAULT_CLOUD_API_ROOT_DIR).join(constants::AGENT_CONFIG_FILE);
+
+        match AgentConfig::load(&path) {
+            Ok(config) => config.hooks,
+            Err(e) => {
+                tracing::warn!("Failed to load agent config, using default hook settings: {:?}", e);
+                HookConfig::default()
+            }
+        }
+    }
+}
+
+/**
+ * Serves Prometheus metrics at `http://{listen_address}/metrics`. Off by default; the
+ * endpoint is unauthenticated, so keep it on a loopback or otherwise private address.
+ * Changes take effect when the agent restarts.
+ */
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    pub listen_address: String,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            enabled: false,
+            listen_address: constants::DEFAULT_METRICS_LISTEN_ADDRESS.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogRotation {
+    Hourly,
+    Daily,
+    Never,
+}
+
+/**
+ * Controls where the agent logs and how much. `level` applies to everything not listed in
+ * `modules`, which maps module paths such as `cloudapi_agent::service` to their own level.
+ * `RUST_LOG`, when set, overrides both. Changes take effect when the agent restarts.
+ */
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    pub level: String,
+    pub modules: BTreeMap<String, String>,
+    pub console: bool,
+    pub file: FileLogConfig,
+    /// Send logs to the systemd journal; ignored on platforms other than Linux.
+    pub journald: bool,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            format: LogFormat::Text,
+            level: "info".to_string(),
+            modules: BTreeMap::new(),
+            console: true,
+            file: FileLogConfig::default(),
+            journald: false,
+        }
+    }
+}
+
+/**
+ * Rotating log files, written under `logs` in the root dir unless `directory` is set.
+ * Only the newest `max_files` files are kept.
+ */
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct FileLogConfig {
+    pub enabled: bool,
+    pub directory: Option<String>,
+    pub rotation: LogRotation,
+    pub max_files: usize,
+}
+
+impl Default for FileLogConfig {
+    fn default() -> Self {
+        FileLogConfig {
+            enabled: true,
+            directory: None,
+            rotation: LogRotation::Daily,
+            max_files: 7,
+        }
+    }
 }
 
 impl AgentConfig {
     pub fn default() -> Self {
         AgentConfig {
-            cloudapi_endpoint: constants::CLOUD_METADATA_V1_ENDPOINT.to_string(),
+            cloudapi_endpoints: vec![
+                constants::CLOUD_METADATA_V1_ENDPOINT.to_string(),
+                constants::CLOUD_METADATA_V2_ENDPOINT.to_string(),
+            ],
+            cloudapi_endpoint: None,
             package_cache: format!("{}\\package-cache", constants::DEFAULT_CLOUD_API_ROOT_DIR),
-            extensions: vec![],
+            poll: PollConfig::default(),
+            resource: None,
+            registration_secret: None,
+            user_data: UserDataConfig::default(),
+            heartbeat: HeartbeatConfig::default(),
+            logging: LoggingConfig::default(),
+            metrics: MetricsConfig::default(),
+            hooks: HookConfig::default(),
         }
     }
 
-    pub fn get_cloudapi_endpoint(&self) -> &String {
-        &self.cloudapi_endpoint
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .context(format!("Failed to read config file: {}", path.to_string_lossy()))?;
+
+        serde_json::from_str(&contents)
+            .context(format!("Failed to parse config file: {}", path.to_string_lossy()))
     }
 
-    pub fn get_package_endpoint(&self) -> String {
-        format!(
-            "{}/{}",
-            self.cloudapi_endpoint,
-            "package"
-        )
+    /**
+     * Returns the configured endpoints in failover order, falling back to the built-in
+     * metadata addresses when none are configured.
+     */
+    pub fn get_cloudapi_endpoints(&self) -> Vec<String> {
+        let mut endpoints: Vec<String> = vec![];
+
+        for endpoint in self.cloudapi_endpoint.iter().chain(self.cloudapi_endpoints.iter()) {
+            let endpoint = endpoint.trim_end_matches('/').to_string();
+
+            if !endpoints.contains(&endpoint) {
+                endpoints.push(endpoint);
+            }
+        }
+
+        if endpoints.is_empty() {
+            return AgentConfig::default().cloudapi_endpoints;
+        }
+
+        endpoints
     }
 
     pub fn get_package_cache(&self) -> &String {
         &self.package_cache
     }
 
-    pub fn get_extensions(&self) -> &Vec<ExtensionState> {
-        &self.extensions
+    pub fn get_poll_config(&self) -> &PollConfig {
+        &self.poll
     }
 
-    pub fn add_extension(&mut self, extension: ExtensionState) {
-        self.extensions.push(extension);
+    pub fn get_registration(&self) -> RegistrationRequest {
+        RegistrationRequest {
+            resource: self.resource.clone(),
+            registration_secret: self.registration_secret.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_endpoint_comes_first_without_duplicates() {
+        let config = AgentConfig {
+            cloudapi_endpoint: Some("http://legacy/".to_string()),
+            cloudapi_endpoints: vec!["http://primary".to_string(), "http://legacy".to_string(), "http://primary/".to_string()],
+            ..AgentConfig::default()
+        };
+
+        assert_eq!(config.get_cloudapi_endpoints(), vec!["http://legacy", "http://primary"]);
     }
 
-    pub fn remove_extension(&mut self, uid: &str) {
-        self.extensions.retain(|ext| ext.uid != uid);
+    #[test]
+    fn falls_back_to_the_builtin_endpoints() {
+        let config = AgentConfig {
+            cloudapi_endpoints: Vec::new(),
+            ..AgentConfig::default()
+        };
+
+        assert_eq!(config.get_cloudapi_endpoints(), AgentConfig::default().cloudapi_endpoints);
     }
-}
\ No newline at end of file
+}
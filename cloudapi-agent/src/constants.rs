@@ -6,5 +6,60 @@ pub const DEFAULT_CLOUD_API_ROOT_DIR: &str = "/var/lib/cloud-api";
 
 pub const CLOUD_METADATA_V1_ENDPOINT: &str = "http://169.254.169.254";
 
-#[allow(dead_code)]
-pub const CLOUD_METADATA_V2_ENDPOINT: &str = "http://168.63.129.16";
\ No newline at end of file
+pub const CLOUD_METADATA_V2_ENDPOINT: &str = "http://168.63.129.16";
+
+pub const AGENT_CONFIG_FILE: &str = "agent.config.json";
+
+pub const DESIRED_STATE_FILE: &str = "desired-state.json";
+
+pub const STATE_DB_FILE: &str = "state.json";
+
+pub const DEFAULT_UNINSTALL_TIMEOUT_SECS: u64 = 300;
+
+pub const DEFAULT_EVENT_HANDLER_TIMEOUT_SECS: u64 = 300;
+
+/// Extension id under which the agent itself is assigned; it is updated through the self-update path.
+pub const AGENT_EXTENSION_ID: &str = "cloudapi-agent";
+
+pub const AGENT_SERVICE_NAME: &str = "cloudapi-agent";
+
+pub const AGENT_UPDATE_STATE_FILE: &str = "agent-update.json";
+
+/// How long a newly installed agent has to report in before it is rolled back.
+pub const AGENT_UPDATE_HEALTH_CHECK_SECS: u64 = 600;
+
+/// How many times a newly installed agent may start without reporting in before it is rolled back.
+pub const AGENT_UPDATE_MAX_BOOT_ATTEMPTS: u32 = 3;
+
+pub const RUNTIME_STATUS_FILE: &str = "agent-status.json";
+
+#[cfg(unix)]
+pub const CONTROL_SOCKET_FILE: &str = "agent.sock";
+
+#[cfg(windows)]
+pub const CONTROL_PIPE_NAME: &str = r"\\.\pipe\cloudapi-agent";
+
+/// Shared secret clients of the control API must present; readable only by root/admin.
+pub const CONTROL_TOKEN_FILE: &str = "control.token";
+
+/// Quiet period after a config change before reconciling, so one save triggers one reconciliation.
+pub const CONFIG_WATCH_DEBOUNCE_MS: u64 = 500;
+
+/// Held exclusively by the running agent so only one instance works on the root dir.
+pub const INSTANCE_LOCK_FILE: &str = "agent.lock";
+
+pub const LOG_DIR: &str = "logs";
+
+pub const LOG_FILE_PREFIX: &str = "cloudapi-agent";
+
+pub const DEFAULT_METRICS_LISTEN_ADDRESS: &str = "127.0.0.1:9464";
+
+pub const RUN_HISTORY_DIR: &str = "run-history";
+
+/// Hook runs kept per extension; older runs are dropped when a new one is recorded.
+pub const RUN_HISTORY_MAX_RUNS: usize = 50;
+
+pub const RUN_HISTORY_MAX_AGE_DAYS: i64 = 30;
+
+/// Longest line of hook output forwarded at once; longer lines are split at this length.
+pub const HOOK_MAX_LINE_BYTES: usize = 16 * 1024;
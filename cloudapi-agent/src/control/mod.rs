@@ -0,0 +1,106 @@
+pub mod server;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::{Mutex, Notify};
+
+use crate::constants;
+use crate::state::RuntimeStatus;
+
+/**
+ * Commands accepted by the local control API. Requests and responses are single lines
+ * of JSON on the control socket (a named pipe on Windows).
+ */
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlCommand {
+    Status,
+    Extensions,
+    /// The most recent hook run of an extension, with its output.
+    ExtensionLogs { id: String },
+    /// Hook runs of an extension, newest first.
+    ExtensionHistory { id: String, limit: Option<usize> },
+    Reconcile,
+    /// Compute what a reconciliation would do against the cached desired state.
+    Plan,
+    RerunHook { id: String },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ControlRequest {
+    pub token: String,
+    #[serde(flatten)]
+    pub command: ControlCommand,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ControlResponse {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/**
+ * State shared between the poll loop and the control server.
+ */
+#[derive(Clone, Default)]
+pub struct ControlHandle {
+    /// Wakes the poll loop to reconcile immediately.
+    pub reconcile_now: Arc<Notify>,
+    /// Held while reconciling or running hooks so they never overlap.
+    pub reconcile_lock: Arc<Mutex<()>>,
+    pub runtime_status: Arc<std::sync::Mutex<Option<RuntimeStatus>>>,
+}
+
+pub fn token_path() -> PathBuf {
+    Path::new(constants::DEFAULT_CLOUD_API_ROOT_DIR).join(constants::CONTROL_TOKEN_FILE)
+}
+
+#[cfg(unix)]
+pub fn socket_path() -> PathBuf {
+    Path::new(constants::DEFAULT_CLOUD_API_ROOT_DIR).join(constants::CONTROL_SOCKET_FILE)
+}
+
+/**
+ * Sends a command to the running agent and returns its result. Fails when no agent is
+ * listening or the caller cannot read the control token.
+ */
+pub async fn send_command(command: ControlCommand) -> Result<Value> {
+    let token = std::fs::read_to_string(token_path())
+        .context("Failed to read the control token; the control API requires root/admin")?;
+
+    let request = ControlRequest { token: token.trim().to_string(), command };
+
+    #[cfg(unix)]
+    let stream = tokio::net::UnixStream::connect(socket_path()).await
+        .context("The agent is not running or its control socket is unavailable")?;
+
+    #[cfg(windows)]
+    let stream = tokio::net::windows::named_pipe::ClientOptions::new()
+        .open(constants::CONTROL_PIPE_NAME)
+        .context("The agent is not running or its control pipe is unavailable")?;
+
+    let (reader, mut writer) = tokio::io::split(stream);
+
+    let mut line = serde_json::to_string(&request)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+
+    let mut response = String::new();
+    BufReader::new(reader).read_line(&mut response).await?;
+
+    let response: ControlResponse = serde_json::from_str(&response)
+        .context("Invalid response from the agent control API")?;
+
+    if !response.ok {
+        return Err(anyhow::anyhow!(response.error.unwrap_or_else(|| "Control command failed".to_string())));
+    }
+
+    Ok(response.result.unwrap_or(Value::Null))
+}
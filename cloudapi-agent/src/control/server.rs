@@ -0,0 +1,320 @@
+use anyhow::{Context, Result};
+use cloudapi_sdk::model::hook::RunTrigger;
+use rand::Rng;
+use serde_json::Value;
+use std::fs;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio_util::sync::CancellationToken;
+
+use crate::config::HookConfig;
+use crate::control::{token_path, ControlCommand, ControlHandle, ControlRequest, ControlResponse};
+use crate::extension::history;
+use crate::extension::{find_extension, find_history_package_id};
+use crate::state::{DesiredState, StateDb};
+
+/**
+ * Serves the control API until cancelled. A fresh token is written on every start so a
+ * client must be able to read it, which only root/admin can.
+ */
+pub async fn serve(handle: ControlHandle, cancellation_token: CancellationToken) -> Result<()> {
+    let token = write_token()?;
+
+    #[cfg(unix)]
+    serve_unix(handle, token, cancellation_token).await?;
+
+    #[cfg(windows)]
+    serve_windows(handle, token, cancellation_token).await?;
+
+    Ok(())
+}
+
+fn write_token() -> Result<String> {
+    let token: String = (0..32).map(|_| format!("{:02x}", rand::rng().random::<u8>())).collect();
+    let path = token_path();
+
+    let _ = fs::remove_file(&path);
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options.open(&path)
+        .context(format!("Failed to create control token: {}", path.to_string_lossy()))?;
+
+    // Restrict the file before the token is written to it.
+    #[cfg(windows)]
+    AdminOnly::new()?.apply_to_file(&path)?;
+
+    std::io::Write::write_all(&mut file, token.as_bytes())
+        .context(format!("Failed to write control token: {}", path.to_string_lossy()))?;
+
+    Ok(token)
+}
+
+#[cfg(unix)]
+async fn serve_unix(handle: ControlHandle, token: String, cancellation_token: CancellationToken) -> Result<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use tokio::net::UnixListener;
+
+    let path = crate::control::socket_path();
+    let _ = fs::remove_file(&path);
+
+    let listener = UnixListener::bind(&path)
+        .context(format!("Failed to bind control socket: {}", path.to_string_lossy()))?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+    let owner_uid = fs::metadata(&path)?.uid();
+
+    tracing::info!("Control API listening on {}", path.to_string_lossy());
+
+    loop {
+        let stream = tokio::select! {
+            _ = cancellation_token.cancelled() => break,
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    tracing::warn!("Failed to accept control connection: {:?}", e);
+                    continue;
+                }
+            },
+        };
+
+        match stream.peer_cred() {
+            Ok(cred) if cred.uid() == 0 || cred.uid() == owner_uid => {}
+            Ok(cred) => {
+                tracing::warn!("Rejected control connection from uid {}", cred.uid());
+                continue;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to read control peer credentials: {:?}", e);
+                continue;
+            }
+        }
+
+        tokio::spawn(handle_connection(stream, handle.clone(), token.clone()));
+    }
+
+    let _ = fs::remove_file(&path);
+
+    Ok(())
+}
+
+#[cfg(windows)]
+async fn serve_windows(handle: ControlHandle, token: String, cancellation_token: CancellationToken) -> Result<()> {
+    use std::time::Duration;
+
+    let pipe_name = crate::constants::CONTROL_PIPE_NAME;
+    let mut security = AdminOnly::new()?;
+    let mut server = create_pipe(pipe_name, true, &mut security)
+        .context(format!("Failed to create control pipe: {}", pipe_name))?;
+
+    tracing::info!("Control API listening on {}", pipe_name);
+
+    loop {
+        tokio::select! {
+            _ = cancellation_token.cancelled() => break,
+            connected = server.connect() => {
+                if let Err(e) = connected {
+                    tracing::warn!("Failed to accept control connection: {:?}", e);
+                    continue;
+                }
+
+                // The connected instance is handed off only once the next one is listening.
+                let next = loop {
+                    match create_pipe(pipe_name, false, &mut security) {
+                        Ok(next) => break next,
+                        Err(e) => {
+                            tracing::warn!("Failed to create control pipe instance, retrying: {:?}", e);
+
+                            tokio::select! {
+                                _ = cancellation_token.cancelled() => return Ok(()),
+                                _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+                            }
+                        }
+                    }
+                };
+
+                let client = std::mem::replace(&mut server, next);
+                tokio::spawn(handle_connection(client, handle.clone(), token.clone()));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(windows)]
+fn create_pipe(pipe_name: &str, first: bool, security: &mut AdminOnly) -> std::io::Result<tokio::net::windows::named_pipe::NamedPipeServer> {
+    let mut options = tokio::net::windows::named_pipe::ServerOptions::new();
+    options.first_pipe_instance(first).reject_remote_clients(true);
+
+    // SAFETY: the attributes and the descriptor they point to outlive the call.
+    unsafe { options.create_with_security_attributes_raw(pipe_name, security.as_raw()) }
+}
+
+/**
+ * A security descriptor whose DACL grants access to SYSTEM and the Administrators group
+ * only, with inherited entries blocked. Used for the control pipe and token file so other
+ * local users cannot talk to the agent.
+ */
+#[cfg(windows)]
+struct AdminOnly {
+    descriptor: windows::Win32::Security::PSECURITY_DESCRIPTOR,
+    attributes: windows::Win32::Security::SECURITY_ATTRIBUTES,
+}
+
+// The descriptor is a heap allocation owned by this value.
+#[cfg(windows)]
+unsafe impl Send for AdminOnly {}
+
+#[cfg(windows)]
+impl AdminOnly {
+    const SDDL: windows::core::PCWSTR = windows::core::w!("D:P(A;;GA;;;SY)(A;;GA;;;BA)");
+
+    fn new() -> Result<Self> {
+        use windows::Win32::Security::Authorization::{ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1};
+        use windows::Win32::Security::{PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES};
+
+        let mut descriptor = PSECURITY_DESCRIPTOR::default();
+
+        unsafe { ConvertStringSecurityDescriptorToSecurityDescriptorW(Self::SDDL, SDDL_REVISION_1, &mut descriptor, None) }
+            .context("Failed to build control security descriptor")?;
+
+        Ok(AdminOnly {
+            descriptor,
+            attributes: SECURITY_ATTRIBUTES {
+                nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+                lpSecurityDescriptor: descriptor.0,
+                bInheritHandle: false.into(),
+            },
+        })
+    }
+
+    fn as_raw(&mut self) -> *mut std::ffi::c_void {
+        &mut self.attributes as *mut _ as *mut std::ffi::c_void
+    }
+
+    fn apply_to_file(&self, path: &std::path::Path) -> Result<()> {
+        use windows::core::HSTRING;
+        use windows::Win32::Security::{SetFileSecurityW, DACL_SECURITY_INFORMATION};
+
+        unsafe { SetFileSecurityW(&HSTRING::from(path.as_os_str()), DACL_SECURITY_INFORMATION, self.descriptor) }.ok()
+            .context(format!("Failed to restrict access to {}", path.to_string_lossy()))
+    }
+}
+
+#[cfg(windows)]
+impl Drop for AdminOnly {
+    fn drop(&mut self) {
+        let _ = unsafe { windows::Win32::Foundation::LocalFree(windows::Win32::Foundation::HLOCAL(self.descriptor.0)) };
+    }
+}
+
+/**
+ * Compares control tokens in time that does not depend on where they first differ.
+ */
+fn tokens_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given.bytes().zip(expected.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+async fn handle_connection<S: AsyncRead + AsyncWrite>(stream: S, handle: ControlHandle, token: String) {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let response = match serde_json::from_str::<ControlRequest>(&line) {
+            Ok(request) if tokens_match(&request.token, &token) => match execute(&handle, request.command).await {
+                Ok(result) => ControlResponse { ok: true, result: Some(result), error: None },
+                Err(e) => ControlResponse { ok: false, result: None, error: Some(format!("{:#}", e)) },
+            },
+            Ok(_) => ControlResponse { ok: false, result: None, error: Some("Invalid control token".to_string()) },
+            Err(e) => ControlResponse { ok: false, result: None, error: Some(format!("Invalid request: {}", e)) },
+        };
+
+        let Ok(mut response) = serde_json::to_string(&response) else {
+            break;
+        };
+        response.push('\n');
+
+        if writer.write_all(response.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn execute(handle: &ControlHandle, command: ControlCommand) -> Result<Value> {
+    tracing::info!("Control command: {:?}", command);
+
+    match command {
+        ControlCommand::Status => {
+            let runtime_status = handle.runtime_status.lock().unwrap().clone();
+            Ok(serde_json::to_value(runtime_status)?)
+        }
+        ControlCommand::Extensions => {
+            let state_db = StateDb::load(&StateDb::default_path())?;
+            Ok(serde_json::to_value(state_db.extensions.values().collect::<Vec<_>>())?)
+        }
+        ControlCommand::ExtensionLogs { id } => {
+            let package_id = lookup_history(&id)?;
+            let run = history::last_run(&package_id)?
+                .context(format!("Extension {} has not run any hooks", package_id))?;
+
+            Ok(serde_json::to_value(run)?)
+        }
+        ControlCommand::ExtensionHistory { id, limit } => {
+            let package_id = lookup_history(&id)?;
+            Ok(serde_json::to_value(history::read_history(&package_id, limit)?)?)
+        }
+        ControlCommand::Reconcile => {
+            handle.reconcile_now.notify_one();
+            Ok(Value::Null)
+        }
+        ControlCommand::Plan => {
+            let desired_state = DesiredState::load(&DesiredState::default_path())?;
+            let state_db = StateDb::load(&StateDb::default_path())?;
+
+            Ok(serde_json::to_value(crate::service::plan::plan_extensions(&desired_state, &state_db)?)?)
+        }
+        ControlCommand::RerunHook { id } => {
+            let (package_id, version) = lookup_extension(&id)?;
+            let _guard = handle.reconcile_lock.lock().await;
+
+            tracing::info!("Re-running install hook of {} {}", package_id, version);
+            let run = crate::extension::install::run_install_script(&package_id, &version, RunTrigger::Rerun, &HookConfig::load_or_default()).await?
+                .context(format!("Extension {} has no install script", package_id))?;
+
+            Ok(serde_json::to_value(run)?)
+        }
+    }
+}
+
+fn lookup_extension(id: &str) -> Result<(String, String)> {
+    let desired_state = DesiredState::load(&DesiredState::default_path())?;
+    let state_db = StateDb::load(&StateDb::default_path())?;
+
+    find_extension(&desired_state, &state_db, id)
+        .context(format!("Extension {} is neither assigned nor tracked", id))
+}
+
+fn lookup_history(id: &str) -> Result<String> {
+    let desired_state = DesiredState::load(&DesiredState::default_path())?;
+    let state_db = StateDb::load(&StateDb::default_path())?;
+
+    find_history_package_id(&desired_state, &state_db, id)
+        .context(format!("Extension {} is neither assigned, tracked nor in the run history", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_exact_token_matches() {
+        assert!(tokens_match("0123abcd", "0123abcd"));
+        assert!(!tokens_match("0123abce", "0123abcd"));
+        assert!(!tokens_match("0123abc", "0123abcd"));
+        assert!(!tokens_match("", "0123abcd"));
+    }
+}
@@ -0,0 +1,131 @@
+use anyhow::Result;
+use cloudapi_sdk::client::CloudApiClient;
+use cloudapi_sdk::model::events::ScheduledEvent;
+use cloudapi_sdk::model::extension::ExtensionStatus;
+use cloudapi_sdk::model::hook::{HookKind, RunTrigger};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::HookConfig;
+use crate::constants;
+use crate::extension::hook::{run_hook, HookInvocation};
+use crate::extension::{get_versioned_extension_dir, read_extension_spec, ExtensionSpec};
+use crate::state::StateDb;
+
+/**
+ * Delivers pending scheduled events to every installed extension whose `extension.spec`
+ * subscribes to the event type. The event is passed as JSON in the `CLOUDAPI_EVENT`
+ * environment variable of the extension's `event_handler_script`.
+ *
+ * An event is acknowledged on the server once it had at least one subscriber and every
+ * subscriber handled it successfully. Each extension handles an event once; failed
+ * deliveries, and acknowledgements the server did not accept, are retried on the next poll.
+ */
+pub async fn forward_scheduled_events(client: &CloudApiClient, state_db: &mut StateDb, hook_config: &HookConfig) -> Result<()> {
+    let scheduled_events = client.get_scheduled_events().await?;
+
+    // Forget events the server no longer reports so the state does not grow without bound.
+    let is_reported = |event_id: &String| scheduled_events.events.iter().any(|event| event.event_id == *event_id);
+    state_db.forwarded_events.retain(|event_id| is_reported(event_id));
+    state_db.event_deliveries.retain(|event_id, _| is_reported(event_id));
+
+    let mut acknowledged = Vec::new();
+
+    for event in &scheduled_events.events {
+        if event.acknowledged_at.is_some() || state_db.forwarded_events.contains(&event.event_id) {
+            continue;
+        }
+
+        tracing::info!("Forwarding scheduled event {} ({:?}, not before {})", event.event_id, event.event_type, event.not_before);
+
+        let mut subscribers = 0;
+        let mut all_succeeded = true;
+        let mut delivered = state_db.event_deliveries.remove(&event.event_id).unwrap_or_default();
+
+        for observed in state_db.extensions.values().filter(|observed| observed.status == ExtensionStatus::Installed) {
+            let versioned_ext_dir = get_versioned_extension_dir(&observed.package_id, &observed.version);
+
+            let extension_spec = match read_extension_spec(&versioned_ext_dir) {
+                Ok(Some(extension_spec)) if extension_spec.is_subscribed_to(event.event_type) => extension_spec,
+                Ok(_) => continue,
+                Err(e) => {
+                    tracing::error!("Failed to read spec of extension {}: {:?}", observed.package_id, e);
+                    continue;
+                }
+            };
+
+            subscribers += 1;
+
+            if delivered.contains(&observed.uid) {
+                continue;
+            }
+
+            match run_event_handler(&observed.package_id, &versioned_ext_dir, &extension_spec, event, hook_config).await {
+                Ok(()) => {
+                    delivered.insert(observed.uid.clone());
+                }
+                Err(e) => {
+                    tracing::error!("Extension {} failed to handle scheduled event {}: {:?}", observed.package_id, event.event_id, e);
+                    all_succeeded = false;
+                }
+            }
+        }
+
+        if subscribers > 0 {
+            state_db.event_deliveries.insert(event.event_id.clone(), delivered);
+        }
+
+        if !all_succeeded {
+            continue;
+        }
+
+        if subscribers > 0 {
+            acknowledged.push(event.event_id.clone());
+        } else {
+            state_db.forwarded_events.insert(event.event_id.clone());
+        }
+    }
+
+    if acknowledged.is_empty() {
+        return Ok(());
+    }
+
+    // Only events the server accepted are done; the rest are acknowledged again next time.
+    client.acknowledge_scheduled_events(acknowledged.clone()).await?;
+
+    for event_id in acknowledged {
+        state_db.event_deliveries.remove(&event_id);
+        state_db.forwarded_events.insert(event_id);
+    }
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "extension", skip_all, fields(id = %package_id, version = %extension_spec.version, event_id = %event.event_id))]
+async fn run_event_handler(package_id: &str, versioned_ext_dir: &Path, extension_spec: &ExtensionSpec, event: &ScheduledEvent, hook_config: &HookConfig) -> Result<()> {
+    let Some(handler_script) = extension_spec.event_handler_script.as_ref().filter(|script| !script.is_empty()) else {
+        return Err(anyhow::anyhow!("Extension subscribes to scheduled events but defines no event_handler_script"));
+    };
+
+    let handler_script = versioned_ext_dir.join(handler_script);
+
+    if !handler_script.exists() {
+        return Err(anyhow::anyhow!("Event handler script not found: {}", handler_script.to_string_lossy()));
+    }
+
+    let run = run_hook(HookInvocation {
+        package_id: package_id.to_string(),
+        version: extension_spec.version.clone(),
+        hook: HookKind::EventHandler,
+        script: handler_script,
+        trigger: RunTrigger::ScheduledEvent,
+        detail: Some(event.event_id.clone()),
+        timeout: Some(Duration::from_secs(constants::DEFAULT_EVENT_HANDLER_TIMEOUT_SECS)),
+        env: vec![("CLOUDAPI_EVENT".to_string(), serde_json::to_string(event)?)],
+    }, hook_config).await;
+
+    match run.error {
+        Some(error) => Err(anyhow::anyhow!(error)),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,305 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use cloudapi_sdk::model::hook::{HookKind, HookRunSummary, RunTrigger};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::constants;
+use crate::storage;
+
+/**
+ * One execution of an extension hook. Runs are appended to the extension's history,
+ * which outlives the extension directory so that uninstalls can be inspected too.
+ */
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HookRun {
+    pub package_id: String,
+    pub version: String,
+    pub hook: HookKind,
+    pub trigger: RunTrigger,
+    #[serde(default)]
+    pub detail: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub duration_ms: u64,
+    /// Missing when the hook could not be started or was killed.
+    pub exit_code: Option<i32>,
+    #[serde(default)]
+    pub timed_out: bool,
+    #[serde(default)]
+    pub error: Option<String>,
+    pub stdout: String,
+    pub stderr: String,
+    #[serde(default)]
+    pub stdout_truncated: bool,
+    #[serde(default)]
+    pub stderr_truncated: bool,
+    /// The full output as it was streamed while the hook ran, up to the log file limit.
+    #[serde(default)]
+    pub log_file: Option<PathBuf>,
+}
+
+impl HookRun {
+    pub fn succeeded(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+
+    pub fn summary(&self) -> HookRunSummary {
+        HookRunSummary {
+            hook: self.hook,
+            version: self.version.clone(),
+            trigger: self.trigger,
+            detail: self.detail.clone(),
+            started_at: self.started_at,
+            finished_at: self.finished_at,
+            duration_ms: self.duration_ms,
+            exit_code: self.exit_code,
+            error: self.error.clone(),
+        }
+    }
+}
+
+/**
+ * Whether `id` can be a package id, which names a file and a directory in the run history
+ * directory and so must not contain separators or consist of dots only.
+ */
+pub fn is_package_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        && !id.chars().all(|c| c == '.')
+}
+
+pub fn history_path(package_id: &str) -> PathBuf {
+    Path::new(constants::DEFAULT_CLOUD_API_ROOT_DIR)
+        .join(constants::RUN_HISTORY_DIR)
+        .join(format!("{}.jsonl", package_id))
+}
+
+/**
+ * Where the output of a run is streamed to while it runs. Names sort by start time so the
+ * newest run's file is the last one in the directory.
+ */
+pub fn log_file_path(package_id: &str, hook: HookKind, started_at: DateTime<Utc>) -> PathBuf {
+    log_dir(package_id).join(format!("{}-{}.log", started_at.format("%Y%m%dT%H%M%S%.3fZ"), hook.as_str()))
+}
+
+pub fn log_dir(package_id: &str) -> PathBuf {
+    Path::new(constants::DEFAULT_CLOUD_API_ROOT_DIR)
+        .join(constants::RUN_HISTORY_DIR)
+        .join(package_id)
+}
+
+/**
+ * The log file of the extension's most recently started run, which may still be running.
+ */
+pub fn latest_log_file(package_id: &str) -> Result<Option<PathBuf>> {
+    let entries = match fs::read_dir(log_dir(package_id)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context(format!("Failed to read run logs of {}", package_id)),
+    };
+
+    Ok(entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("log"))
+        .max())
+}
+
+/**
+ * Appends a run to the extension's history. Once the history exceeds its retention
+ * limits it is rewritten without the runs that fell out of them, whose log files are
+ * deleted.
+ */
+pub fn append(run: &HookRun) -> Result<()> {
+    append_to(&history_path(&run.package_id), run)
+}
+
+fn append_to(path: &Path, run: &HookRun) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut line = serde_json::to_string(run)?;
+    line.push('\n');
+
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .context(format!("Failed to append to run history: {}", path.to_string_lossy()))?;
+
+    let runs = read_all(path)?;
+    let cutoff = Utc::now() - chrono::Duration::days(constants::RUN_HISTORY_MAX_AGE_DAYS);
+    let mut retained: Vec<&HookRun> = runs.iter().filter(|run| run.started_at >= cutoff).collect();
+    retained.drain(..retained.len().saturating_sub(constants::RUN_HISTORY_MAX_RUNS));
+
+    if retained.len() < runs.len() {
+        let mut contents = Vec::new();
+
+        for run in &retained {
+            serde_json::to_writer(&mut contents, run)?;
+            contents.push(b'\n');
+        }
+
+        storage::write_atomic(path, &contents)?;
+
+        for dropped in runs.iter().filter(|run| !retained.iter().any(|kept| kept.started_at == run.started_at && kept.hook == run.hook)) {
+            if let Some(log_file) = &dropped.log_file {
+                let _ = fs::remove_file(log_file);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * The extension's runs, newest first, up to `limit` when given.
+ */
+pub fn read_history(package_id: &str, limit: Option<usize>) -> Result<Vec<HookRun>> {
+    let mut runs = read_all(&history_path(package_id))?;
+    runs.reverse();
+
+    if let Some(limit) = limit {
+        runs.truncate(limit);
+    }
+
+    Ok(runs)
+}
+
+pub fn last_run(package_id: &str) -> Result<Option<HookRun>> {
+    Ok(read_history(package_id, Some(1))?.pop())
+}
+
+fn read_all(path: &Path) -> Result<Vec<HookRun>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context(format!("Failed to read run history: {}", path.to_string_lossy())),
+    };
+
+    // A line cut short by a crash mid-append is skipped rather than failing the whole history.
+    Ok(contents.lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(run) => Some(run),
+            Err(e) => {
+                tracing::warn!("Skipping unreadable run history entry in {}: {}", path.to_string_lossy(), e);
+                None
+            }
+        })
+        .collect())
+}
+
+/**
+ * Keeps at most `limit` bytes of output, preferring the end where errors usually are.
+ * Returns the kept output and whether anything was dropped.
+ */
+pub fn truncate_output(output: &[u8], limit: usize) -> (String, bool) {
+    if output.len() <= limit {
+        return (String::from_utf8_lossy(output).to_string(), false);
+    }
+
+    let tail = String::from_utf8_lossy(&output[output.len() - limit..]);
+
+    // The cut may land inside a multi-byte character; drop the replacement it produces.
+    (tail.trim_start_matches(char::REPLACEMENT_CHARACTER).to_string(), true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(started_at: DateTime<Utc>, log_file: Option<PathBuf>) -> HookRun {
+        HookRun {
+            package_id: "test-sample".to_string(),
+            version: "1.0.0".to_string(),
+            hook: HookKind::Install,
+            trigger: RunTrigger::Reconcile,
+            detail: None,
+            started_at,
+            finished_at: started_at,
+            duration_ms: 0,
+            exit_code: Some(0),
+            timed_out: false,
+            error: None,
+            stdout: String::new(),
+            stderr: String::new(),
+            stdout_truncated: false,
+            stderr_truncated: false,
+            log_file,
+        }
+    }
+
+    #[test]
+    fn only_the_newest_runs_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test-sample.jsonl");
+        let first = Utc::now() - chrono::Duration::minutes(100);
+
+        for i in 0..constants::RUN_HISTORY_MAX_RUNS + 5 {
+            append_to(&path, &run(first + chrono::Duration::minutes(i as i64), None)).unwrap();
+        }
+
+        let runs = read_all(&path).unwrap();
+        assert_eq!(runs.len(), constants::RUN_HISTORY_MAX_RUNS);
+        assert_eq!(runs[0].started_at, first + chrono::Duration::minutes(5));
+    }
+
+    #[test]
+    fn runs_past_the_maximum_age_are_dropped_with_their_log_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test-sample.jsonl");
+        let old_log = dir.path().join("old.log");
+        fs::write(&old_log, "output").unwrap();
+
+        let old = Utc::now() - chrono::Duration::days(constants::RUN_HISTORY_MAX_AGE_DAYS + 1);
+        append_to(&path, &run(old, Some(old_log.clone()))).unwrap();
+        append_to(&path, &run(Utc::now(), None)).unwrap();
+
+        let runs = read_all(&path).unwrap();
+        assert_eq!(runs.len(), 1);
+        assert!(runs[0].started_at > old);
+        assert!(!old_log.exists());
+    }
+
+    #[test]
+    fn a_line_cut_short_by_a_crash_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test-sample.jsonl");
+        append_to(&path, &run(Utc::now(), None)).unwrap();
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"{\"package_id\": \"te").unwrap();
+
+        assert_eq!(read_all(&path).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn package_ids_cannot_leave_the_run_history_directory() {
+        assert!(is_package_id("test-sample"));
+        assert!(is_package_id("test.sample_2"));
+
+        for id in ["", ".", "..", "../..", "..\\..", "test/../../etc", "C:\\test", "test sample"] {
+            assert!(!is_package_id(id), "{}", id);
+        }
+    }
+
+    #[test]
+    fn output_within_the_limit_is_kept_whole() {
+        assert_eq!(truncate_output(b"done", 4), ("done".to_string(), false));
+    }
+
+    #[test]
+    fn long_output_keeps_its_end() {
+        assert_eq!(truncate_output(b"step 1\nstep 2\nfailed", 6), ("failed".to_string(), true));
+    }
+
+    #[test]
+    fn a_cut_inside_a_character_drops_the_partial_character() {
+        // "é" is two bytes; keeping the last two bytes starts halfway through it.
+        assert_eq!(truncate_output("café!".as_bytes(), 2), ("!".to_string(), true));
+    }
+}
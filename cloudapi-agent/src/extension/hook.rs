@@ -0,0 +1,349 @@
+use chrono::{SecondsFormat, Utc};
+use cloudapi_sdk::model::hook::{HookKind, RunTrigger};
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::mpsc;
+
+use crate::config::HookConfig;
+use crate::constants;
+use crate::extension::history::{self, HookRun};
+use crate::metrics::{self, HookExit};
+
+/**
+ * A hook script to run for an extension, and why.
+ */
+pub struct HookInvocation {
+    pub package_id: String,
+    pub version: String,
+    pub hook: HookKind,
+    pub script: PathBuf,
+    pub trigger: RunTrigger,
+    pub detail: Option<String>,
+    /// The script is killed when it runs longer; `None` waits for it indefinitely.
+    pub timeout: Option<Duration>,
+    pub env: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+impl OutputStream {
+    fn as_str(&self) -> &'static str {
+        match self {
+            OutputStream::Stdout => "stdout",
+            OutputStream::Stderr => "stderr",
+        }
+    }
+}
+
+/**
+ * The end of one output stream, bounded to `limit` bytes.
+ */
+struct OutputTail {
+    buffer: Vec<u8>,
+    limit: usize,
+    dropped: bool,
+}
+
+impl OutputTail {
+    fn new(limit: usize) -> Self {
+        OutputTail { buffer: Vec::new(), limit, dropped: false }
+    }
+
+    fn push_line(&mut self, line: &[u8]) {
+        self.buffer.extend_from_slice(line);
+        self.buffer.push(b'\n');
+
+        // Trim in batches rather than on every line.
+        if self.buffer.len() > self.limit.saturating_mul(2) {
+            self.buffer.drain(..self.buffer.len() - self.limit);
+            self.dropped = true;
+        }
+    }
+
+    fn finish(self) -> (String, bool) {
+        let (output, truncated) = history::truncate_output(&self.buffer, self.limit);
+        (output, truncated || self.dropped)
+    }
+}
+
+/**
+ * The per-run log file operators can tail while a hook runs. Writing stops, with a note,
+ * once the file reaches its size limit; failing to write only disables the file.
+ */
+struct RunLogFile {
+    file: Option<File>,
+    written: u64,
+    limit: u64,
+}
+
+impl RunLogFile {
+    async fn create(path: &Path, limit: u64) -> Self {
+        let file = match path.parent().map(tokio::fs::create_dir_all) {
+            Some(create_dir) => create_dir.await.and(File::create(path).await),
+            None => File::create(path).await,
+        };
+
+        match file {
+            Ok(file) => RunLogFile { file: Some(file), written: 0, limit },
+            Err(e) => {
+                tracing::warn!("Failed to create hook log file {}: {:?}", path.to_string_lossy(), e);
+                RunLogFile { file: None, written: 0, limit }
+            }
+        }
+    }
+
+    async fn write_line(&mut self, stream: OutputStream, line: &str) {
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+
+        let entry = format!("{} [{}] {}\n", Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true), stream.as_str(), line);
+
+        let result = if self.written + entry.len() as u64 > self.limit {
+            let note = format!("--- log file limit of {} bytes reached, further output is not written ---\n", self.limit);
+            let result = file.write_all(note.as_bytes()).await;
+            self.file = None;
+            result
+        } else {
+            self.written += entry.len() as u64;
+            file.write_all(entry.as_bytes()).await
+        };
+
+        if let Err(e) = result {
+            tracing::warn!("Failed to write hook log file: {:?}", e);
+            self.file = None;
+        }
+    }
+}
+
+/**
+ * Runs a hook script with PowerShell and records the run in the extension's history and
+ * in metrics. Output is streamed line by line into the log and the run's log file as the
+ * script produces it. Failing to start or finish the script is reported in the returned
+ * run rather than as an error, so every attempt is recorded.
+ */
+pub async fn run_hook(invocation: HookInvocation, config: &HookConfig) -> HookRun {
+    let started_at = Utc::now();
+    let started = Instant::now();
+    let log_file = history::log_file_path(&invocation.package_id, invocation.hook, started_at);
+
+    tracing::info!(
+        "Running {} hook of {} {} ({:?}, timeout: {:?}), streaming output to {}",
+        invocation.hook.as_str(),
+        invocation.package_id,
+        invocation.version,
+        invocation.trigger,
+        invocation.timeout,
+        log_file.to_string_lossy()
+    );
+
+    let mut run = HookRun {
+        package_id: invocation.package_id,
+        version: invocation.version,
+        hook: invocation.hook,
+        trigger: invocation.trigger,
+        detail: invocation.detail,
+        started_at,
+        finished_at: started_at,
+        duration_ms: 0,
+        exit_code: None,
+        timed_out: false,
+        error: None,
+        stdout: String::new(),
+        stderr: String::new(),
+        stdout_truncated: false,
+        stderr_truncated: false,
+        log_file: Some(log_file.clone()),
+    };
+
+    let mut stdout = OutputTail::new(config.max_captured_bytes);
+    let mut stderr = OutputTail::new(config.max_captured_bytes);
+    let mut log = RunLogFile::create(&log_file, config.max_log_file_bytes).await;
+
+    let spawned = Command::new("pwsh")
+        .arg("-NoProfile")
+        .arg("-ExecutionPolicy").arg("Bypass")
+        .arg("-File").arg(&invocation.script)
+        .envs(invocation.env.iter().map(|(key, value)| (key, value)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn();
+
+    let exit = match spawned {
+        Ok(mut child) => {
+            let (sender, mut lines) = mpsc::channel(256);
+
+            if let Some(child_stdout) = child.stdout.take() {
+                tokio::spawn(forward_lines(child_stdout, OutputStream::Stdout, sender.clone()));
+            }
+
+            if let Some(child_stderr) = child.stderr.take() {
+                tokio::spawn(forward_lines(child_stderr, OutputStream::Stderr, sender));
+            }
+
+            let hook = run.hook.as_str();
+            let streamed = async {
+                while let Some((stream, line)) = lines.recv().await {
+                    let text = String::from_utf8_lossy(&line);
+                    tracing::info!(hook, stream = stream.as_str(), "{}", text);
+                    log.write_line(stream, &text).await;
+
+                    match stream {
+                        OutputStream::Stdout => stdout.push_line(&line),
+                        OutputStream::Stderr => stderr.push_line(&line),
+                    }
+                }
+
+                child.wait().await
+            };
+
+            let status: Result<std::io::Result<ExitStatus>, _> = match invocation.timeout {
+                Some(timeout) => tokio::time::timeout(timeout, streamed).await,
+                None => Ok(streamed.await),
+            };
+
+            match status {
+                Ok(Ok(status)) => {
+                    let code = status.code().unwrap_or(-1);
+                    run.exit_code = Some(code);
+
+                    if !status.success() {
+                        run.error = Some(format!("Hook failed with code {}", code));
+                    }
+
+                    HookExit::Code(code)
+                }
+                Ok(Err(e)) => {
+                    run.error = Some(format!("Failed to wait for hook: {}", e));
+                    HookExit::Error
+                }
+                Err(_) => {
+                    let _ = child.kill().await;
+                    run.timed_out = true;
+                    run.error = Some(format!("Hook timed out after {:?}", invocation.timeout.unwrap_or_default()));
+                    HookExit::TimedOut
+                }
+            }
+        }
+        Err(e) => {
+            run.error = Some(format!("Failed to execute hook: {}", e));
+            HookExit::Error
+        }
+    };
+
+    run.finished_at = Utc::now();
+    run.duration_ms = started.elapsed().as_millis() as u64;
+    (run.stdout, run.stdout_truncated) = stdout.finish();
+    (run.stderr, run.stderr_truncated) = stderr.finish();
+
+    metrics::metrics().record_hook(&run.package_id, run.hook.as_str(), exit, started.elapsed());
+
+    match &run.error {
+        Some(error) => tracing::error!("{} hook of {} {}: {}", run.hook.as_str(), run.package_id, run.version, error),
+        None => tracing::info!("{} hook of {} {} completed in {}ms", run.hook.as_str(), run.package_id, run.version, run.duration_ms),
+    }
+
+    if let Err(e) = history::append(&run) {
+        tracing::warn!("Failed to record hook run of {}: {:?}", run.package_id, e);
+    }
+
+    run
+}
+
+/**
+ * Sends each line of a child's output stream, without its line ending, until the stream
+ * closes or nobody is listening any more. Lines longer than `HOOK_MAX_LINE_BYTES` are
+ * split so a script printing without newlines cannot grow the buffer without limit.
+ */
+async fn forward_lines<R: AsyncRead + Unpin>(reader: R, stream: OutputStream, sender: mpsc::Sender<(OutputStream, Vec<u8>)>) {
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+
+    loop {
+        let available = match reader.fill_buf().await {
+            Ok(available) => available,
+            Err(e) => {
+                tracing::warn!("Failed to read hook {}: {:?}", stream.as_str(), e);
+                break;
+            }
+        };
+
+        if available.is_empty() {
+            if !line.is_empty() {
+                let _ = sender.send((stream, trim_line_ending(line))).await;
+            }
+
+            break;
+        }
+
+        let chunk = &available[..available.len().min(constants::HOOK_MAX_LINE_BYTES - line.len())];
+        let (consumed, complete) = match chunk.iter().position(|byte| *byte == b'\n') {
+            Some(end) => {
+                line.extend_from_slice(&chunk[..end]);
+                (end + 1, true)
+            }
+            None => {
+                line.extend_from_slice(chunk);
+                (chunk.len(), line.len() >= constants::HOOK_MAX_LINE_BYTES)
+            }
+        };
+        reader.consume(consumed);
+
+        if complete && sender.send((stream, trim_line_ending(std::mem::take(&mut line)))).await.is_err() {
+            break;
+        }
+    }
+}
+
+fn trim_line_ending(mut line: Vec<u8>) -> Vec<u8> {
+    while line.last().is_some_and(|byte| *byte == b'\n' || *byte == b'\r') {
+        line.pop();
+    }
+
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn forwarded(output: &[u8]) -> Vec<Vec<u8>> {
+        let (sender, mut receiver) = mpsc::channel(16);
+        let reader = tokio::spawn(forward_lines(std::io::Cursor::new(output.to_vec()), OutputStream::Stdout, sender));
+
+        let mut lines = Vec::new();
+        while let Some((_, line)) = receiver.recv().await {
+            lines.push(line);
+        }
+
+        reader.await.unwrap();
+        lines
+    }
+
+    #[tokio::test]
+    async fn forwards_lines_without_endings() {
+        let lines = forwarded(b"first\r\nsecond\n\nlast").await;
+
+        assert_eq!(lines, vec![b"first".to_vec(), b"second".to_vec(), Vec::new(), b"last".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn splits_lines_longer_than_the_limit() {
+        let mut output = vec![b'x'; constants::HOOK_MAX_LINE_BYTES * 2 + 10];
+        output.extend_from_slice(b"\nnext\n");
+
+        let lines = forwarded(&output).await;
+
+        assert_eq!(lines.iter().map(Vec::len).collect::<Vec<_>>(), vec![constants::HOOK_MAX_LINE_BYTES, constants::HOOK_MAX_LINE_BYTES, 10, 4]);
+    }
+}
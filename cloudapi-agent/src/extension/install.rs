@@ -0,0 +1,107 @@
+use anyhow::Result;
+use cloudapi_sdk::model::hook::{HookKind, RunTrigger};
+use std::fs;
+use std::path::Path;
+
+use crate::config::HookConfig;
+use crate::extension::get_versioned_extension_dir;
+use crate::extension::history::HookRun;
+use crate::extension::hook::{run_hook, HookInvocation};
+
+/**
+ * Runs the `install.ps1` of an installed extension version, if it has one, recording the
+ * run in the extension's history. Only a successful run marks the script as executed with
+ * `ran.lock`, so a failed install runs it again on the next attempt. Returns `None` when
+ * the extension has no install script.
+ */
+pub async fn run_install_script(package_id: &str, version: &str, trigger: RunTrigger, hook_config: &HookConfig) -> Result<Option<HookRun>> {
+    let versioned_ext_dir = get_versioned_extension_dir(package_id, version);
+    let ps_script = versioned_ext_dir.join("install.ps1");
+
+    if !ps_script.exists() {
+        return Ok(None);
+    }
+
+    let run = run_hook(HookInvocation {
+        package_id: package_id.to_string(),
+        version: version.to_string(),
+        hook: HookKind::Install,
+        script: ps_script,
+        trigger,
+        detail: None,
+        timeout: None,
+        env: Vec::new(),
+    }, hook_config).await;
+
+    if run.exit_code.is_none() {
+        return Err(anyhow::anyhow!(run.error.unwrap_or_else(|| "Failed to execute PowerShell script".to_string())));
+    }
+
+    record_install_run(&versioned_ext_dir, &run)?;
+
+    Ok(Some(run))
+}
+
+/**
+ * Whether the install script of the extension version in `versioned_ext_dir` has already
+ * run successfully.
+ */
+pub fn install_script_ran(versioned_ext_dir: &Path) -> bool {
+    versioned_ext_dir.join("ran.lock").exists()
+}
+
+fn record_install_run(versioned_ext_dir: &Path, run: &HookRun) -> Result<()> {
+    if !run.succeeded() {
+        return Err(anyhow::anyhow!("PowerShell script failed with code {}", run.exit_code.unwrap_or(-1)));
+    }
+
+    fs::write(versioned_ext_dir.join("ran.lock"), run.finished_at.to_rfc3339().as_bytes())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn run(exit_code: Option<i32>) -> HookRun {
+        HookRun {
+            package_id: "test-sample".to_string(),
+            version: "1.0.0".to_string(),
+            hook: HookKind::Install,
+            trigger: RunTrigger::Reconcile,
+            detail: None,
+            started_at: Utc::now(),
+            finished_at: Utc::now(),
+            duration_ms: 0,
+            exit_code,
+            timed_out: false,
+            error: None,
+            stdout: String::new(),
+            stderr: String::new(),
+            stdout_truncated: false,
+            stderr_truncated: false,
+            log_file: None,
+        }
+    }
+
+    #[test]
+    fn a_failed_install_script_runs_again_on_the_retry() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let error = record_install_run(dir.path(), &run(Some(1))).unwrap_err();
+
+        assert_eq!(error.to_string(), "PowerShell script failed with code 1");
+        assert!(!install_script_ran(dir.path()));
+    }
+
+    #[test]
+    fn a_successful_install_script_is_not_run_again() {
+        let dir = tempfile::tempdir().unwrap();
+
+        record_install_run(dir.path(), &run(Some(0))).unwrap();
+
+        assert!(install_script_ran(dir.path()));
+    }
+}
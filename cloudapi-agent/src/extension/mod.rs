@@ -1,15 +1,16 @@
+pub mod events;
+pub mod history;
+pub mod hook;
 pub mod uninstall;
 pub mod install;
 
+use anyhow::{Context, Result};
+use cloudapi_sdk::model::events::ScheduledEventType;
 use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ExtensionRunLog {
-    pub executed_at: String,
-    pub exit_code: i32,
-    pub stdout: String,
-    pub stderr: String,
-}
+use crate::constants;
+use crate::state::{DesiredState, StateDb};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ExtensionSpec {
@@ -21,4 +22,66 @@ pub struct ExtensionSpec {
     pub uninstall_script: Option<String>,
     pub config_schema: Option<String>,
     pub one_time_script: Option<String>,
-}
\ No newline at end of file
+    pub uninstall_timeout_secs: Option<u64>,
+    pub uninstall_on_orphan: Option<bool>,
+    pub event_subscriptions: Option<Vec<ScheduledEventType>>,
+    pub event_handler_script: Option<String>,
+}
+
+impl ExtensionSpec {
+    pub fn is_subscribed_to(&self, event_type: ScheduledEventType) -> bool {
+        self.event_subscriptions.as_ref().is_some_and(|subscriptions| subscriptions.contains(&event_type))
+    }
+}
+
+pub fn get_versioned_extension_dir(package_id: &str, version: &str) -> PathBuf {
+    PathBuf::from(format!("{}\\extensions\\{}\\v{}", constants::DEFAULT_CLOUD_API_ROOT_DIR, package_id, version))
+}
+
+pub fn read_extension_spec(versioned_ext_dir: &Path) -> Result<Option<ExtensionSpec>> {
+    let extension_spec_path = versioned_ext_dir.join("extension.spec");
+
+    tracing::info!("Looking for extension spec file: {}", extension_spec_path.to_string_lossy());
+    let spec_contents = match std::fs::read_to_string(&extension_spec_path) {
+        Ok(contents) => contents,
+        Err(_) => {
+            tracing::warn!("Extension spec file not found: {}", extension_spec_path.to_string_lossy());
+            return Ok(Option::None);
+        }
+    };
+
+    tracing::info!("Found extension spec file: {}", extension_spec_path.to_string_lossy());
+    let extension_spec: ExtensionSpec = serde_json::from_str(&spec_contents)
+        .context(format!("Failed to parse extension spec file: {}", extension_spec_path.to_string_lossy()))?;
+
+    Ok(Some(extension_spec))
+}
+
+/**
+ * Finds the package id and version of an extension by uid, package id or extension id,
+ * preferring what is installed over what is assigned.
+ */
+pub fn find_extension(desired_state: &DesiredState, state_db: &StateDb, id: &str) -> Option<(String, String)> {
+    if let Some(observed) = state_db.extensions.values().find(|observed| observed.uid == id || observed.package_id == id) {
+        return Some((observed.package_id.clone(), observed.version.clone()));
+    }
+
+    if let Some(desired) = desired_state.get_extensions().iter().find(|desired| desired.uid == id || desired.id == id || desired.get_package_id() == id) {
+        return Some((desired.get_package_id(), desired.version.clone()));
+    }
+
+    state_db.extensions.values()
+        .find(|observed| observed.package_id.ends_with(&format!("-{}", id)))
+        .map(|observed| (observed.package_id.clone(), observed.version.clone()))
+}
+
+/**
+ * The package id whose run history `id` refers to. Unlike `find_extension` this also
+ * finds extensions that were uninstalled and are only left in the run history, as long as
+ * `id` is a plain package id that cannot name a file outside of it.
+ */
+pub fn find_history_package_id(desired_state: &DesiredState, state_db: &StateDb, id: &str) -> Option<String> {
+    find_extension(desired_state, state_db, id)
+        .map(|(package_id, _)| package_id)
+        .or_else(|| (history::is_package_id(id) && history::history_path(id).exists()).then(|| id.to_string()))
+}
@@ -1,132 +1,240 @@
-use anyhow::{Context, Result};
+use anyhow::Result;
 use cloudapi_sdk::model::extension::{ExtensionState, ExtensionStatus};
-use tokio::process::Command;
+use cloudapi_sdk::model::hook::{HookKind, RunTrigger};
 use std::path::PathBuf;
+use std::time::Duration;
 use std::{fs, path::Path};
-use crate::config::AgentConfig;
+use crate::config::HookConfig;
 use crate::constants;
+use crate::extension::hook::{run_hook, HookInvocation};
+use crate::extension::{get_versioned_extension_dir, read_extension_spec, ExtensionSpec};
+use crate::state::{DesiredState, ObservedExtension, StateDb};
 
-pub async fn uninstall_extensions(config: &AgentConfig) -> Result<()> {
-    for state in config.get_extensions() 
+pub async fn uninstall_extensions(desired_state: &DesiredState, state_db: &mut StateDb, hook_config: &HookConfig) -> Result<()> {
+    for state in desired_state.get_extensions() 
     {
         if state.status == ExtensionStatus::Uninstalling 
         {
-            uninstall_extension(state).await?;
+            if already_uninstalled(state, state_db) {
+                continue;
+            }
+
+            let mut observed = ObservedExtension::new(state, ExtensionStatus::Uninstalled);
+
+            if let Err(e) = uninstall_extension(&state.get_package_id(), &state.version, RunTrigger::Reconcile, hook_config).await {
+                tracing::error!("Failed to uninstall extension {}: {:?}", state.get_package_id(), e);
+                observed.status = ExtensionStatus::Failed;
+                observed.last_error = Some(format!("{:#}", e));
+            }
+
+            state_db.upsert(observed);
         }
     }
 
-    clean_extension_dir(config)?;
+    remove_stale_extensions(desired_state, state_db, hook_config).await;
+
+    clean_extension_dir(desired_state, state_db, hook_config).await?;
 
     Ok(())
 }
 
-fn get_extension_uninstall_script_path(state: &ExtensionState) -> Result<Option<PathBuf>> {
-    let versioned_ext_dir = format!("{}\\extensions\\{}\\v{}", constants::DEFAULT_CLOUD_API_ROOT_DIR, state.get_package_id(), state.version);
-    let extension_spec_path = Path::new(&versioned_ext_dir).join("extension.spec");
+/**
+ * Whether an extension assigned as uninstalling was already uninstalled at that version,
+ * so its uninstall hook is not run again on every reconciliation.
+ */
+fn already_uninstalled(extension: &ExtensionState, state_db: &StateDb) -> bool {
+    state_db.get(&extension.uid)
+        .is_some_and(|observed| observed.status == ExtensionStatus::Uninstalled && observed.version == extension.version)
+}
+
+/**
+ * The server's extension list is authoritative: anything the agent has observed on this
+ * machine that is no longer assigned is uninstalled (running its uninstall hook) and
+ * dropped from the state database. Failed uninstalls stay tracked and are retried on the
+ * next reconciliation.
+ */
+async fn remove_stale_extensions(desired_state: &DesiredState, state_db: &mut StateDb, hook_config: &HookConfig) {
+    for mut observed in find_stale_extensions(desired_state, state_db) {
+        tracing::info!("Extension {} (uid: {}) is no longer assigned. Uninstalling...", observed.package_id, observed.uid);
+
+        if observed.status != ExtensionStatus::Uninstalled {
+            observed.status = ExtensionStatus::Uninstalling;
+            state_db.upsert(observed.clone());
+
+            if let Err(e) = uninstall_extension(&observed.package_id, &observed.version, RunTrigger::Unassigned, hook_config).await {
+                tracing::error!("Failed to uninstall stale extension {}: {:?}", observed.package_id, e);
+                observed.status = ExtensionStatus::Failed;
+                observed.last_error = Some(format!("{:#}", e));
+                state_db.upsert(observed);
+                continue;
+            }
+        }
+
+        state_db.remove(&observed.uid);
+        tracing::info!("Removed stale extension {} from local state.", observed.package_id);
+    }
+}
+
+/**
+ * Extensions tracked in the state database that are no longer assigned. Nothing is stale
+ * against a desired state that was not actually received from the server.
+ */
+pub fn find_stale_extensions(desired_state: &DesiredState, state_db: &StateDb) -> Vec<ObservedExtension> {
+    if desired_state.fetched_at.is_none() {
+        return Vec::new();
+    }
+
+    state_db.extensions.values()
+        .filter(|observed| !desired_state.get_extensions().iter().any(|ext| ext.uid == observed.uid))
+        .cloned()
+        .collect()
+}
 
-    tracing::info!("Looking for extension spec file: {}", extension_spec_path.to_string_lossy());
-    let spec_contents = std::fs::read_to_string(&extension_spec_path);
+/**
+ * Extension directories that are neither assigned nor tracked in the state database.
+ * Until a desired state has been received from the server nothing is known to be an
+ * orphan, since a first start after an upgrade may not track its extensions yet.
+ */
+pub fn find_orphaned_extensions(desired_state: &DesiredState, state_db: &StateDb) -> Result<Vec<String>> {
+    find_orphaned_extensions_in(&format!("{}\\extensions", constants::DEFAULT_CLOUD_API_ROOT_DIR), desired_state, state_db)
+}
 
-    if spec_contents.is_err() {
-        tracing::warn!("Extension spec file not found: {}", extension_spec_path.to_string_lossy());
-        return Ok(Option::None);
+fn find_orphaned_extensions_in(extensions_dir: &str, desired_state: &DesiredState, state_db: &StateDb) -> Result<Vec<String>> {
+    if desired_state.fetched_at.is_none() {
+        return Ok(Vec::new());
     }
 
-    tracing::info!("Found extension spec file: {}", extension_spec_path.to_string_lossy());
-    let extension_spec: crate::extension::ExtensionSpec = serde_json::from_str(&spec_contents.unwrap())
-        .context(format!("Failed to parse extension spec file: {}", extension_spec_path.to_string_lossy()))?;
-   
-    let ext_uninstall_script = extension_spec.uninstall_script.clone();
+    let installed_extensions: Vec<String> = fs::read_dir(extensions_dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+
+    let config_extensions: Vec<String> = desired_state.get_extensions().iter()
+        .map(|ext| ext.get_package_id())
+        .chain(state_db.extensions.values().map(|observed| observed.package_id.clone()))
+        .collect();
+
+    Ok(installed_extensions.into_iter()
+        .filter(|ext| !config_extensions.contains(ext))
+        .collect())
+}
 
-    tracing::info!("Parsed extension spec: ...");
-    if ext_uninstall_script.is_some() && !ext_uninstall_script.as_ref().unwrap().is_empty() {
-        let spec_uninstall_script = Path::new(&versioned_ext_dir).join(ext_uninstall_script.clone().unwrap());
+fn get_extension_uninstall_script_path(versioned_ext_dir: &Path, extension_spec: Option<&ExtensionSpec>) -> Option<PathBuf> {
+    let ext_uninstall_script = extension_spec.and_then(|spec| spec.uninstall_script.clone());
+
+    if let Some(ext_uninstall_script) = ext_uninstall_script.filter(|script| !script.is_empty()) {
+        let spec_uninstall_script = versioned_ext_dir.join(&ext_uninstall_script);
 
         if spec_uninstall_script.exists() {
-            return Ok(Some(spec_uninstall_script));
+            return Some(spec_uninstall_script);
         } else {
-            tracing::warn!("Extension defined an uninstall script that was not found: {}", ext_uninstall_script.clone().unwrap());
+            tracing::warn!("Extension defined an uninstall script that was not found: {}", ext_uninstall_script);
         }
     }
 
-    let implicit_uninstall_script = Path::new(&versioned_ext_dir).join("uninstall.ps1");
+    let implicit_uninstall_script = versioned_ext_dir.join("uninstall.ps1");
 
     if implicit_uninstall_script.exists() {
         tracing::info!("Found implicit uninstall script: {}", implicit_uninstall_script.to_string_lossy());
-        return Ok(Some(implicit_uninstall_script));
+        return Some(implicit_uninstall_script);
     }
 
-    tracing::warn!("No uninstall script found for extension: {}", state.get_package_id());
-    Ok(Option::None)
+    tracing::warn!("No uninstall script found in: {}", versioned_ext_dir.to_string_lossy());
+    Option::None
 }
 
-async fn uninstall_extension(state: &ExtensionState) -> Result<()> {
-    let ext_dir = format!("{}\\extensions\\{}", constants::DEFAULT_CLOUD_API_ROOT_DIR, state.get_package_id());
-    tracing::info!("Uninstalling extension: {}", ext_dir);
+/**
+ * Runs the uninstall hook of a single versioned extension directory, if it has one.
+ * The script is killed if it does not finish within the spec's `uninstall_timeout_secs`.
+ */
+async fn run_uninstall_hook(package_id: &str, version: &str, versioned_ext_dir: &Path, extension_spec: Option<&ExtensionSpec>, trigger: RunTrigger, hook_config: &HookConfig) -> Result<()> {
+    tracing::info!("Looking for uninstall script for extension: {}", package_id);
+
+    let Some(ps_script) = get_extension_uninstall_script_path(versioned_ext_dir, extension_spec) else {
+        tracing::info!("No uninstall script found for extension: {}", package_id);
+        return Ok(());
+    };
+
+    let timeout = Duration::from_secs(
+        extension_spec
+            .and_then(|spec| spec.uninstall_timeout_secs)
+            .unwrap_or(constants::DEFAULT_UNINSTALL_TIMEOUT_SECS)
+    );
 
-    if !Path::new(&ext_dir).exists() {
-        tracing::warn!("Extension {} not found for uninstallation.", state.get_package_id());
+    let run = run_hook(HookInvocation {
+        package_id: package_id.to_string(),
+        version: version.to_string(),
+        hook: HookKind::Uninstall,
+        script: ps_script,
+        trigger,
+        detail: None,
+        timeout: Some(timeout),
+        env: Vec::new(),
+    }, hook_config).await;
+
+    if run.succeeded() {
         return Ok(());
     }
 
-    // === One-off PowerShell execution ===
-    tracing::info!("Looking for uninstall script for extension: {}", state.get_package_id());
-    let ps_script = get_extension_uninstall_script_path(state)?;
-
-    if ps_script.is_some() {
-        tracing::info!("Running uninstall script for extension: {}", state.get_package_id());
-        let output = Command::new("pwsh")
-            .arg("-NoProfile")
-            .arg("-ExecutionPolicy").arg("Bypass")
-            .arg("-File").arg(ps_script.unwrap())
-            .output()
-            .await;
-
-        if output.is_err() {
-            tracing::error!("Failed to execute uninstall script: {:?}", output.err().unwrap());
-            return Err(anyhow::anyhow!("Failed to execute uninstall script"));
-        }
+    Err(anyhow::anyhow!(run.error.unwrap_or_else(|| "Failed to execute uninstall script".to_string())))
+}
 
-        tracing::info!("Uninstall script output: {:?}", output);
+#[tracing::instrument(name = "extension", skip_all, fields(id = %package_id, version = %version))]
+pub async fn uninstall_extension(package_id: &str, version: &str, trigger: RunTrigger, hook_config: &HookConfig) -> Result<()> {
+    let versioned_ext_dir = get_versioned_extension_dir(package_id, version);
+    tracing::info!("Uninstalling extension: {}", versioned_ext_dir.to_string_lossy());
+
+    if !versioned_ext_dir.exists() {
+        tracing::warn!("Extension {} {} not found for uninstallation.", package_id, version);
+        return Ok(());
     }
-    else {
-        tracing::info!("No uninstall script found for extension: {}", state.get_package_id());
+
+    // === One-off PowerShell execution ===
+    let extension_spec = read_extension_spec(&versioned_ext_dir)?;
+
+    run_uninstall_hook(package_id, version, &versioned_ext_dir, extension_spec.as_ref(), trigger, hook_config).await?;
+
+    // Only this version is removed; another uid may have installed a different one alongside it.
+    if fs::remove_dir_all(&versioned_ext_dir).is_err() {
+        tracing::error!("Failed to remove extension directory: {}", versioned_ext_dir.to_string_lossy());
+    } else {
+        tracing::info!("Extension {} {} uninstalled successfully.", package_id, version);
     }
 
-    if fs::remove_dir_all(&ext_dir).is_err() {
+    let ext_dir = format!("{}\\extensions\\{}", constants::DEFAULT_CLOUD_API_ROOT_DIR, package_id);
+
+    // The package directory only holds the `VERSION` marker once no version is left in it.
+    let has_versions = fs::read_dir(&ext_dir)
+        .map(|entries| entries.filter_map(|entry| entry.ok()).any(|entry| entry.path().is_dir()))
+        .unwrap_or(true);
+
+    if !has_versions && fs::remove_dir_all(&ext_dir).is_err() {
         tracing::error!("Failed to remove extension directory: {}", ext_dir);
-    } else {
-        tracing::info!("Extension {} uninstalled successfully.", state.get_package_id());
     }
 
     Ok(())
 }
 
 /**
- * Removes any extensions that are not in the config but are present in the extensions directory.
+ * Removes any extensions that are not in the desired state or the state database but are present in the extensions directory.
  * This is useful for cleaning up old extensions that are no longer needed.
  * 
- * Note this function does not call the uninstall script for the extensions, it simply removes the directory.
+ * Every version directory of an orphaned extension has its uninstall hook run before the
+ * directory is deleted, unless its `extension.spec` sets `uninstall_on_orphan` to false.
+ * A version whose hook fails or times out is kept, with the failure in its run history,
+ * so the hook is retried on the next reconciliation.
  */
-fn clean_extension_dir(config: &AgentConfig) -> Result<()> {
+async fn clean_extension_dir(desired_state: &DesiredState, state_db: &StateDb, hook_config: &HookConfig) -> Result<()> {
     // Check for any extensions that are not in the config but are installed
-    let installed_extensions: Vec<String> = fs::read_dir(format!("{}\\extensions", constants::DEFAULT_CLOUD_API_ROOT_DIR))?
-        .filter_map(|entry| entry.ok())
-        .filter_map(|entry| entry.file_name().into_string().ok())
-        .collect();
-
-    let config_extensions: Vec<String> = config.get_extensions().iter()
-        .map(|ext| ext.get_package_id())
-        .collect();
-
-    let extensions_to_remove: Vec<String> = installed_extensions.into_iter()
-        .filter(|ext| !config_extensions.contains(ext))
-        .collect();
-
-    for ext in extensions_to_remove {
+    for ext in find_orphaned_extensions(desired_state, state_db)? {
         let ext_dir = format!("{}\\extensions\\{}", constants::DEFAULT_CLOUD_API_ROOT_DIR, ext);
         tracing::info!("Removing extension: {}", ext_dir);
 
+        if !remove_orphaned_versions(&ext, Path::new(&ext_dir), hook_config).await {
+            tracing::warn!("Keeping orphaned extension {} until its uninstall hooks succeed.", ext);
+            continue;
+        }
+
         if fs::remove_dir_all(&ext_dir).is_err() {
             tracing::error!("Failed to remove extension directory: {}", ext_dir);
         } else {
@@ -135,4 +243,160 @@ fn clean_extension_dir(config: &AgentConfig) -> Result<()> {
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/**
+ * Runs the uninstall hook of every version directory of an orphaned extension and removes
+ * the directories whose hook succeeded. Returns whether every version was removed.
+ */
+#[tracing::instrument(name = "extension", skip_all, fields(id = %package_id))]
+async fn remove_orphaned_versions(package_id: &str, ext_dir: &Path, hook_config: &HookConfig) -> bool {
+    let Ok(entries) = fs::read_dir(ext_dir) else {
+        return true;
+    };
+
+    let versioned_ext_dirs: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+
+    let mut all_removed = true;
+
+    for versioned_ext_dir in versioned_ext_dirs {
+        if let Err(e) = run_orphan_uninstall_hook(package_id, &versioned_ext_dir, hook_config).await {
+            tracing::error!("Uninstall hook failed for orphaned extension {}: {:?}", package_id, e);
+            all_removed = false;
+            continue;
+        }
+
+        if fs::remove_dir_all(&versioned_ext_dir).is_err() {
+            tracing::error!("Failed to remove extension directory: {}", versioned_ext_dir.to_string_lossy());
+            all_removed = false;
+        }
+    }
+
+    all_removed
+}
+
+async fn run_orphan_uninstall_hook(package_id: &str, versioned_ext_dir: &Path, hook_config: &HookConfig) -> Result<()> {
+    let extension_spec = match read_extension_spec(versioned_ext_dir) {
+        Ok(extension_spec) => extension_spec,
+        Err(e) => {
+            // Without a readable spec the hook can never run, so retrying would keep the directory forever.
+            tracing::error!("Skipping uninstall hook for orphaned extension {}: {:?}", package_id, e);
+            return Ok(());
+        }
+    };
+
+    if extension_spec.as_ref().and_then(|spec| spec.uninstall_on_orphan) == Some(false) {
+        tracing::info!("Extension {} opted out of orphan uninstall: {}", package_id, versioned_ext_dir.to_string_lossy());
+        return Ok(());
+    }
+
+    // Version directories are named `v{version}`; the spec is authoritative when present.
+    let version = extension_spec.as_ref()
+        .map(|spec| spec.version.clone())
+        .or_else(|| versioned_ext_dir.file_name().map(|name| name.to_string_lossy().trim_start_matches('v').to_string()))
+        .unwrap_or_default();
+
+    run_uninstall_hook(package_id, &version, versioned_ext_dir, extension_spec.as_ref(), RunTrigger::Orphaned, hook_config).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extension(uid: &str, id: &str) -> ExtensionState {
+        let mut extension = ExtensionState::new(uid, id, "1.0.0");
+        extension.set_publisher("test");
+        extension
+    }
+
+    fn state_db(extensions: &[ExtensionState]) -> StateDb {
+        let mut state_db = StateDb::default();
+
+        for extension in extensions {
+            state_db.upsert(ObservedExtension::new(extension, ExtensionStatus::Installed));
+        }
+
+        state_db
+    }
+
+    #[test]
+    fn unassigned_tracked_extensions_are_stale() {
+        let desired_state = DesiredState::new(vec![extension("uid-1", "kept")]);
+        let state_db = state_db(&[extension("uid-1", "kept"), extension("uid-2", "dropped")]);
+
+        let stale = find_stale_extensions(&desired_state, &state_db);
+
+        assert_eq!(stale.iter().map(|observed| observed.uid.as_str()).collect::<Vec<_>>(), vec!["uid-2"]);
+    }
+
+    #[test]
+    fn nothing_is_stale_before_the_desired_state_is_fetched() {
+        let desired_state = DesiredState { fetched_at: None, extensions: Vec::new() };
+        let state_db = state_db(&[extension("uid-1", "tracked")]);
+
+        assert!(find_stale_extensions(&desired_state, &state_db).is_empty());
+    }
+
+    #[test]
+    fn an_uninstalled_version_is_not_uninstalled_again() {
+        let mut uninstalling = extension("uid-1", "sample");
+        uninstalling.set_status(ExtensionStatus::Uninstalling);
+
+        let mut state_db = StateDb::default();
+        assert!(!already_uninstalled(&uninstalling, &state_db));
+
+        state_db.upsert(ObservedExtension::new(&uninstalling, ExtensionStatus::Failed));
+        assert!(!already_uninstalled(&uninstalling, &state_db));
+
+        state_db.upsert(ObservedExtension::new(&uninstalling, ExtensionStatus::Uninstalled));
+        assert!(already_uninstalled(&uninstalling, &state_db));
+
+        let mut newer = extension("uid-1", "sample");
+        newer.version = "2.0.0".to_string();
+        assert!(!already_uninstalled(&newer, &state_db));
+    }
+
+    fn extensions_dir(package_ids: &[&str]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+
+        for package_id in package_ids {
+            fs::create_dir_all(dir.path().join(package_id).join("v1.0.0")).unwrap();
+        }
+
+        dir
+    }
+
+    #[test]
+    fn directories_neither_assigned_nor_tracked_are_orphaned() {
+        let dir = extensions_dir(&["test-assigned", "test-tracked", "test-orphan"]);
+        let desired_state = DesiredState::new(vec![extension("uid-1", "assigned")]);
+        let state_db = state_db(&[extension("uid-2", "tracked")]);
+
+        let orphaned = find_orphaned_extensions_in(&dir.path().to_string_lossy(), &desired_state, &state_db).unwrap();
+
+        assert_eq!(orphaned, vec!["test-orphan"]);
+    }
+
+    #[test]
+    fn nothing_is_orphaned_before_the_desired_state_is_fetched() {
+        let dir = extensions_dir(&["test-untracked"]);
+        let desired_state = DesiredState { fetched_at: None, extensions: Vec::new() };
+
+        let orphaned = find_orphaned_extensions_in(&dir.path().to_string_lossy(), &desired_state, &StateDb::default()).unwrap();
+
+        assert!(orphaned.is_empty());
+    }
+
+    #[tokio::test]
+    async fn orphaned_versions_without_an_uninstall_hook_are_removed() {
+        let dir = extensions_dir(&["test-orphan"]);
+        let ext_dir = dir.path().join("test-orphan");
+
+        assert!(remove_orphaned_versions("test-orphan", &ext_dir, &HookConfig::default()).await);
+        assert!(!ext_dir.join("v1.0.0").exists());
+    }
+}
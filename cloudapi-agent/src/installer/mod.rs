@@ -0,0 +1,154 @@
+#[cfg(unix)]
+mod systemd;
+
+#[cfg(windows)]
+mod windows_service;
+
+use anyhow::{Context, Result};
+use cloudapi_sdk::model::hook::RunTrigger;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cli::{InstallArgs, UninstallArgs};
+use crate::config::{AgentConfig, HookConfig};
+use crate::constants;
+use crate::service::setup;
+use crate::state::StateDb;
+use crate::storage;
+
+#[cfg(unix)]
+use systemd as platform;
+
+#[cfg(windows)]
+use windows_service as platform;
+
+/**
+ * Path the agent binary is installed to; the service runs it from here.
+ */
+pub fn installed_binary_path() -> PathBuf {
+    Path::new(constants::DEFAULT_CLOUD_API_ROOT_DIR)
+        .join("bin")
+        .join(format!("{}{}", constants::AGENT_SERVICE_NAME, std::env::consts::EXE_SUFFIX))
+}
+
+pub async fn install(args: &InstallArgs) -> Result<()> {
+    setup::create_application_data_dir(constants::DEFAULT_CLOUD_API_ROOT_DIR)?;
+
+    let config_file = Path::new(constants::DEFAULT_CLOUD_API_ROOT_DIR).join(constants::AGENT_CONFIG_FILE);
+
+    if config_file.exists() && !args.force {
+        tracing::info!("Keeping existing agent config at {} (use --force to overwrite).", config_file.to_string_lossy());
+    } else {
+        let mut config = AgentConfig::default();
+
+        if !args.endpoints.is_empty() {
+            config.cloudapi_endpoints = args.endpoints.clone();
+        }
+
+        if let Some(package_cache) = &args.package_cache {
+            config.package_cache = package_cache.clone();
+        }
+
+        config.registration_secret = args.registration_secret.clone();
+
+        storage::write_json_atomic(&config_file, &config)?;
+        tracing::info!("Wrote agent config to {}", config_file.to_string_lossy());
+    }
+
+    let binary = install_binary()?;
+
+    if args.no_service {
+        tracing::info!("Skipping service registration.");
+        return Ok(());
+    }
+
+    platform::install_service(&binary).await?;
+    tracing::info!("Installed and started service {}.", constants::AGENT_SERVICE_NAME);
+
+    Ok(())
+}
+
+pub async fn uninstall(args: &UninstallArgs) -> Result<()> {
+    platform::uninstall_service().await?;
+    tracing::info!("Removed service {}.", constants::AGENT_SERVICE_NAME);
+
+    if args.purge {
+        uninstall_tracked_extensions().await;
+
+        match fs::remove_dir_all(constants::DEFAULT_CLOUD_API_ROOT_DIR) {
+            Ok(()) => tracing::info!("Removed data directory {}", constants::DEFAULT_CLOUD_API_ROOT_DIR),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).context(format!("Failed to remove data directory: {}", constants::DEFAULT_CLOUD_API_ROOT_DIR)),
+        }
+
+        return Ok(());
+    }
+
+    let binary = installed_binary_path();
+
+    if binary.exists() {
+        fs::remove_file(&binary)
+            .context(format!("Failed to remove agent binary: {}", binary.to_string_lossy()))?;
+        tracing::info!("Removed agent binary {}", binary.to_string_lossy());
+    }
+
+    Ok(())
+}
+
+/**
+ * Copies the running binary to the install location unless it already runs from there.
+ * The copy is renamed into place, since an installed agent that is running cannot be
+ * overwritten (ETXTBSY on Linux, a sharing violation on Windows).
+ */
+fn install_binary() -> Result<PathBuf> {
+    let current = std::env::current_exe()?;
+    let binary = installed_binary_path();
+
+    if current == binary {
+        return Ok(binary);
+    }
+
+    fs::create_dir_all(binary.parent().context("Install path has no parent directory")?)?;
+
+    let staged = binary.with_extension("new");
+    fs::copy(&current, &staged)
+        .context(format!("Failed to copy agent binary to {}", staged.to_string_lossy()))?;
+
+    // Windows cannot replace a running executable, but it can move it aside.
+    #[cfg(windows)]
+    if binary.exists() {
+        let replaced = binary.with_extension("replaced");
+        let _ = fs::remove_file(&replaced);
+        fs::rename(&binary, &replaced)
+            .context(format!("Failed to move aside agent binary: {}", binary.to_string_lossy()))?;
+    }
+
+    fs::rename(&staged, &binary)
+        .context(format!("Failed to install agent binary to {}", binary.to_string_lossy()))?;
+
+    tracing::info!("Installed agent binary to {}", binary.to_string_lossy());
+
+    Ok(binary)
+}
+
+/**
+ * Runs the uninstall hooks of every extension the agent tracks, so purging the data
+ * directory does not leave extension side effects behind.
+ */
+async fn uninstall_tracked_extensions() {
+    let state_db = match StateDb::load(&StateDb::default_path()) {
+        Ok(state_db) => state_db,
+        Err(e) => {
+            tracing::warn!("Failed to load state database, skipping extension uninstall: {:?}", e);
+            return;
+        }
+    };
+
+    let hook_config = HookConfig::load_or_default();
+
+    for observed in state_db.extensions.values() {
+        if let Err(e) = crate::extension::uninstall::uninstall_extension(&observed.package_id, &observed.version, RunTrigger::Purge, &hook_config).await {
+            tracing::error!("Failed to uninstall extension {}: {:?}", observed.package_id, e);
+        }
+    }
+}
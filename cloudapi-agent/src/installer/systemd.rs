@@ -0,0 +1,77 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+use crate::constants;
+
+const SYSTEMD_UNIT_DIR: &str = "/etc/systemd/system";
+
+fn unit_path() -> PathBuf {
+    Path::new(SYSTEMD_UNIT_DIR).join(format!("{}.service", constants::AGENT_SERVICE_NAME))
+}
+
+fn unit_file(binary: &Path) -> String {
+    format!(
+        "[Unit]\n\
+         Description=Cloud API guest agent\n\
+         Wants=network-online.target\n\
+         After=network-online.target\n\
+         \n\
+         [Service]\n\
+         Type=simple\n\
+         ExecStart={}\n\
+         Restart=always\n\
+         RestartSec=5\n\
+         \n\
+         [Install]\n\
+         WantedBy=multi-user.target\n",
+        binary.to_string_lossy()
+    )
+}
+
+async fn systemctl(args: &[&str]) -> Result<()> {
+    let status = Command::new("systemctl")
+        .args(args)
+        .status()
+        .await
+        .context("Failed to execute systemctl")?;
+
+    if !status.success() {
+        return Err(anyhow::anyhow!("systemctl {} failed with {}", args.join(" "), status));
+    }
+
+    Ok(())
+}
+
+pub async fn install_service(binary: &Path) -> Result<()> {
+    let unit_path = unit_path();
+    let unit_name = format!("{}.service", constants::AGENT_SERVICE_NAME);
+
+    fs::write(&unit_path, unit_file(binary))
+        .context(format!("Failed to write systemd unit: {}", unit_path.to_string_lossy()))?;
+    tracing::info!("Wrote systemd unit {}", unit_path.to_string_lossy());
+
+    systemctl(&["daemon-reload"]).await?;
+    systemctl(&["enable", &unit_name]).await?;
+
+    // Restart rather than start so a running agent picks up the newly installed binary.
+    systemctl(&["restart", &unit_name]).await
+}
+
+pub async fn uninstall_service() -> Result<()> {
+    let unit_path = unit_path();
+    let unit_name = format!("{}.service", constants::AGENT_SERVICE_NAME);
+
+    if !unit_path.exists() {
+        tracing::info!("Systemd unit {} is not installed.", unit_path.to_string_lossy());
+        return Ok(());
+    }
+
+    systemctl(&["disable", "--now", &unit_name]).await?;
+
+    fs::remove_file(&unit_path)
+        .context(format!("Failed to remove systemd unit: {}", unit_path.to_string_lossy()))?;
+
+    systemctl(&["daemon-reload"]).await
+}
@@ -0,0 +1,73 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+use tokio::process::Command;
+
+use crate::constants;
+
+async fn sc(args: &[&str]) -> Result<std::process::ExitStatus> {
+    Command::new("sc.exe")
+        .args(args)
+        .status()
+        .await
+        .context("Failed to execute sc.exe")
+}
+
+/**
+ * Creates and starts the service. An existing service is pointed at `binary` and
+ * restarted instead, so installing over an earlier install upgrades it.
+ */
+pub async fn install_service(binary: &Path) -> Result<()> {
+    let bin_path = format!("\"{}\"", binary.to_string_lossy());
+
+    if sc(&["query", constants::AGENT_SERVICE_NAME]).await?.success() {
+        tracing::info!("Service {} already exists, updating it.", constants::AGENT_SERVICE_NAME);
+
+        let status = sc(&["config", constants::AGENT_SERVICE_NAME, "binPath=", &bin_path, "start=", "auto"]).await?;
+
+        if !status.success() {
+            return Err(anyhow::anyhow!("Failed to update service {}: {}", constants::AGENT_SERVICE_NAME, status));
+        }
+
+        // sc.exe only requests a stop, so let Restart-Service wait for it before starting.
+        let status = Command::new("pwsh")
+            .arg("-NoProfile")
+            .arg("-Command")
+            .arg(format!("Restart-Service -Name {} -Force", constants::AGENT_SERVICE_NAME))
+            .status()
+            .await
+            .context("Failed to execute pwsh")?;
+
+        if !status.success() {
+            return Err(anyhow::anyhow!("Failed to restart service {}: {}", constants::AGENT_SERVICE_NAME, status));
+        }
+
+        return Ok(());
+    }
+
+    let status = sc(&["create", constants::AGENT_SERVICE_NAME, "binPath=", &bin_path, "start=", "auto", "DisplayName=", "Cloud API guest agent"]).await?;
+
+    if !status.success() {
+        return Err(anyhow::anyhow!("Failed to create service {}: {}", constants::AGENT_SERVICE_NAME, status));
+    }
+
+    let status = sc(&["start", constants::AGENT_SERVICE_NAME]).await?;
+
+    if !status.success() {
+        return Err(anyhow::anyhow!("Failed to start service {}: {}", constants::AGENT_SERVICE_NAME, status));
+    }
+
+    Ok(())
+}
+
+pub async fn uninstall_service() -> Result<()> {
+    // Stopping fails when the service is not running, which is fine here.
+    let _ = sc(&["stop", constants::AGENT_SERVICE_NAME]).await?;
+
+    let status = sc(&["delete", constants::AGENT_SERVICE_NAME]).await?;
+
+    if !status.success() {
+        tracing::warn!("Service {} could not be deleted ({}); it may not be installed.", constants::AGENT_SERVICE_NAME, status);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,134 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+
+use crate::constants;
+use crate::storage;
+
+/// Who holds the instance lock, recorded in a `.pid` file next to the lock file.
+#[derive(Debug, Serialize, Deserialize)]
+struct LockOwner {
+    pid: u32,
+    started_at: String,
+}
+
+/**
+ * Exclusive lock on the agent root dir (flock on Unix, a locked handle on Windows). The
+ * operating system releases it when the process exits, so a crash never leaves the root
+ * dir locked. The owner is kept in a separate `.pid` file, since a file locked with
+ * `LockFileEx` cannot be read by other processes; it is removed on a clean shutdown,
+ * which is how a lock left behind by a crashed instance is told apart.
+ */
+pub struct InstanceLock {
+    _file: File,
+    owner_path: PathBuf,
+}
+
+impl InstanceLock {
+    pub fn default_path() -> PathBuf {
+        Path::new(constants::DEFAULT_CLOUD_API_ROOT_DIR).join(constants::INSTANCE_LOCK_FILE)
+    }
+
+    pub fn acquire(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let owner_path = path.with_extension("pid");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .context(format!("Failed to open lock file: {}", path.to_string_lossy()))?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let owner = read_owner(&owner_path)
+                    .map(|owner| format!(" (pid {}, started {})", owner.pid, owner.started_at))
+                    .unwrap_or_default();
+
+                return Err(anyhow::anyhow!("Another cloudapi-agent instance is running{}; lock held on {}", owner, path.to_string_lossy()));
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(e).context(format!("Failed to lock {}", path.to_string_lossy()));
+            }
+        }
+
+        if let Some(stale) = read_owner(&owner_path) {
+            tracing::warn!("Recovered stale lock from pid {} (started {}); the previous instance did not shut down cleanly.", stale.pid, stale.started_at);
+        }
+
+        let owner = LockOwner {
+            pid: std::process::id(),
+            started_at: Utc::now().to_rfc3339(),
+        };
+
+        storage::write_json_atomic(&owner_path, &owner)?;
+
+        tracing::info!("Acquired instance lock {} (pid {}).", path.to_string_lossy(), owner.pid);
+
+        Ok(InstanceLock { _file: file, owner_path })
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        // Remove the owner so the next start does not report a stale lock; the lock itself
+        // is released when the file is closed.
+        if let Err(e) = std::fs::remove_file(&self.owner_path) {
+            tracing::warn!("Failed to remove lock owner {}: {:?}", self.owner_path.to_string_lossy(), e);
+        }
+    }
+}
+
+fn read_owner(path: &Path) -> Option<LockOwner> {
+    storage::read_json(path).ok().flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_second_instance_is_refused_with_the_owner_pid() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agent.lock");
+        let _lock = InstanceLock::acquire(&path).unwrap();
+
+        let error = InstanceLock::acquire(&path).err().unwrap().to_string();
+
+        assert!(error.starts_with("Another cloudapi-agent instance is running"), "{}", error);
+        assert!(error.contains(&format!("pid {}", std::process::id())), "{}", error);
+    }
+
+    #[test]
+    fn the_lock_is_released_and_the_owner_removed_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agent.lock");
+
+        let lock = InstanceLock::acquire(&path).unwrap();
+        assert!(path.with_extension("pid").exists());
+        drop(lock);
+
+        assert!(!path.with_extension("pid").exists());
+        assert!(InstanceLock::acquire(&path).is_ok());
+    }
+
+    #[test]
+    fn a_stale_owner_left_by_a_crash_is_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agent.lock");
+        let stale = LockOwner { pid: 0, started_at: "2020-01-01T00:00:00Z".to_string() };
+        storage::write_json_atomic(&path.with_extension("pid"), &stale).unwrap();
+
+        let _lock = InstanceLock::acquire(&path).unwrap();
+
+        let owner = read_owner(&path.with_extension("pid")).unwrap();
+        assert_eq!(owner.pid, std::process::id());
+    }
+}
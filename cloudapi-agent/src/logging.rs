@@ -0,0 +1,123 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+
+use crate::config::{LogFormat, LogRotation, LoggingConfig};
+use crate::constants;
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/**
+ * Logging for one-off commands: plain text on stderr so command output on stdout stays clean.
+ */
+pub fn init_cli() {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn")))
+        .init();
+}
+
+/**
+ * Logging for the agent service, configured from `AgentConfig`. The returned guards flush
+ * the file sink and must be held until the agent exits.
+ */
+pub fn init_service(config: &LoggingConfig) -> Result<Vec<WorkerGuard>> {
+    let mut layers: Vec<BoxedLayer> = vec![];
+    let mut guards = vec![];
+
+    if config.console {
+        layers.push(format_layer(config.format, std::io::stderr, true));
+    }
+
+    if config.file.enabled {
+        let directory = config.file.directory.as_ref()
+            .map(PathBuf::from)
+            .unwrap_or_else(|| Path::new(constants::DEFAULT_CLOUD_API_ROOT_DIR).join(constants::LOG_DIR));
+
+        // The appender prunes old files before it creates the directory, so create it first.
+        std::fs::create_dir_all(&directory)
+            .context(format!("Failed to create log directory: {}", directory.to_string_lossy()))?;
+
+        let appender = RollingFileAppender::builder()
+            .rotation(match config.file.rotation {
+                LogRotation::Hourly => Rotation::HOURLY,
+                LogRotation::Daily => Rotation::DAILY,
+                LogRotation::Never => Rotation::NEVER,
+            })
+            .filename_prefix(constants::LOG_FILE_PREFIX)
+            .filename_suffix("log")
+            .max_log_files(config.file.max_files.max(1))
+            .build(&directory)
+            .context(format!("Failed to open log directory: {}", directory.to_string_lossy()))?;
+
+        let (writer, guard) = tracing_appender::non_blocking(appender);
+        layers.push(format_layer(config.format, writer, false));
+        guards.push(guard);
+    }
+
+    #[cfg(target_os = "linux")]
+    if config.journald {
+        match tracing_journald::layer() {
+            Ok(layer) => layers.push(Box::new(layer)),
+            Err(e) => eprintln!("Failed to connect to journald, not logging to the journal: {}", e),
+        }
+    }
+
+    tracing_subscriber::registry()
+        .with(layers.with_filter(env_filter(config)))
+        .try_init()
+        .context("Failed to initialize logging")?;
+
+    Ok(guards)
+}
+
+fn format_layer<W>(format: LogFormat, writer: W, ansi: bool) -> BoxedLayer
+where
+    W: for<'writer> tracing_subscriber::fmt::MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer);
+
+    match format {
+        LogFormat::Text => Box::new(layer.with_ansi(ansi)),
+        LogFormat::Json => Box::new(layer.json().with_current_span(true).with_span_list(true)),
+    }
+}
+
+/**
+ * Builds the filter from `RUST_LOG` or the configured levels. A level that does not parse
+ * is reported on stderr and replaced by `info` (or dropped for a module) rather than
+ * keeping the agent from starting.
+ */
+fn env_filter(config: &LoggingConfig) -> EnvFilter {
+    if let Ok(filter) = EnvFilter::try_from_default_env() {
+        return filter;
+    }
+
+    let level = config.level.parse::<LevelFilter>().unwrap_or_else(|_| {
+        eprintln!("Invalid logging.level {}, using info", config.level);
+        LevelFilter::INFO
+    });
+
+    let modules = config.modules.iter().filter_map(|(module, level)| match level.parse::<LevelFilter>() {
+        Ok(level) => Some(format!("{}={}", module, level)),
+        Err(_) => {
+            eprintln!("Invalid level {} for logging.modules.{}, ignoring it", level, module);
+            None
+        }
+    });
+
+    let directives = std::iter::once(level.to_string())
+        .chain(modules)
+        .collect::<Vec<_>>()
+        .join(",");
+
+    EnvFilter::try_new(&directives).unwrap_or_else(|e| {
+        eprintln!("Invalid log levels {}, using info: {}", directives, e);
+        EnvFilter::new("info")
+    })
+}
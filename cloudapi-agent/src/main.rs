@@ -1,16 +1,41 @@
+mod cli;
 mod config;
 mod constants;
+mod control;
+mod installer;
 mod service;
 mod extension;
+mod instance;
+mod logging;
+mod metrics;
+mod state;
+mod storage;
+mod user_data;
 use anyhow::Result;
+use clap::Parser;
+use cli::{Cli, Command};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
+    let cli = Cli::parse();
+
+    match &cli.command {
+        Some(Command::Run) | None => {}
+        Some(command) => {
+            logging::init_cli();
+            return cli::run_command(command).await;
+        }
+    }
+
+    let config_file = std::path::Path::new(constants::DEFAULT_CLOUD_API_ROOT_DIR).join(constants::AGENT_CONFIG_FILE);
+    let logging_config = config::AgentConfig::load(&config_file)
+        .map(|config| config.logging)
+        .unwrap_or_default();
+    let _log_guards = logging::init_service(&logging_config)?;
 
     if !check_system_configuration() {
         tracing::warn!("Service configuration not found.");
-        tracing::info!("Please run the installer to set up the service.");
+        tracing::info!("Please run `cloudapi-agent install` to set up the service.");
         tracing::info!("Press Ctrl-C to exit.");
         
         service::wait_for_shutdown_signal().await?;
@@ -19,10 +44,16 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
-    let dependency = assert_command_installed("pwsh").await;
+    let _instance_lock = instance::InstanceLock::acquire(&instance::InstanceLock::default_path())?;
+
+    // Only the instance holding the lock may count boot attempts or roll back an update,
+    // and it does so before anything else that could fail.
+    if let Err(e) = service::update::on_startup().await {
+        tracing::error!("Failed to check pending agent update: {:?}", e);
+    }
 
-    if dependency.is_err() {
-        tracing::error!("Dependency check failed: {}", dependency.unwrap_err());
+    if let Err(e) = assert_command_installed("pwsh").await {
+        tracing::error!("Dependency check failed: {}", e);
         return Err(anyhow::anyhow!("Dependency check failed"));
     }
 
@@ -32,7 +63,7 @@ async fn main() -> Result<()> {
 }
 
 fn check_system_configuration() -> bool {
-    let config_file = format!("{}/agent.config.json", constants::DEFAULT_CLOUD_API_ROOT_DIR);
+    let config_file = format!("{}/{}", constants::DEFAULT_CLOUD_API_ROOT_DIR, constants::AGENT_CONFIG_FILE);
     tracing::info!("Checking for service configuration at: {}", config_file);
 
     if std::path::Path::new(&config_file).exists() {
@@ -42,7 +73,7 @@ fn check_system_configuration() -> bool {
 
     tracing::warn!("Service configuration not found.");
 
-    return false;
+    false
 }
 
 async fn assert_command_installed(cmd: &str) -> Result<()> {
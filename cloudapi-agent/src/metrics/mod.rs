@@ -0,0 +1,211 @@
+pub mod server;
+
+use cloudapi_sdk::model::extension::ExtensionStatus;
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::metrics::histogram::{exponential_buckets, Histogram};
+use prometheus_client::registry::{Registry, Unit};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use crate::service::schedule::PollOutcome;
+use crate::state::StateDb;
+
+const EXTENSION_STATUSES: [ExtensionStatus; 7] = [
+    ExtensionStatus::NotInstalled,
+    ExtensionStatus::Installing,
+    ExtensionStatus::Installed,
+    ExtensionStatus::Uninstalling,
+    ExtensionStatus::Uninstalled,
+    ExtensionStatus::Failed,
+    ExtensionStatus::Disabled,
+];
+
+#[derive(Debug, Clone, Copy)]
+pub enum DownloadResult {
+    Downloaded,
+    CacheHit,
+    Failed,
+}
+
+/**
+ * How a hook ended, reported as the `exit_code` label. Hooks that never produced an exit
+ * code are labelled `timeout` or `error` instead.
+ */
+#[derive(Debug, Clone, Copy)]
+pub enum HookExit {
+    Code(i32),
+    TimedOut,
+    Error,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct OutcomeLabels {
+    outcome: String,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct DownloadLabels {
+    result: String,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct HookLabels {
+    extension: String,
+    hook: String,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct HookExitLabels {
+    extension: String,
+    hook: String,
+    exit_code: String,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct StatusLabels {
+    status: String,
+}
+
+type HistogramFamily<L> = Family<L, Histogram, fn() -> Histogram>;
+
+/**
+ * Agent metrics, recorded from wherever the work happens and served in the Prometheus
+ * text format when `metrics.enabled` is set.
+ */
+pub struct AgentMetrics {
+    registry: Registry,
+    reconcile_cycles: Family<OutcomeLabels, Counter>,
+    reconcile_duration: Histogram,
+    poll_failures: Counter,
+    downloads: Family<DownloadLabels, Counter>,
+    download_bytes: Counter,
+    download_duration: Histogram,
+    hook_executions: Family<HookExitLabels, Counter>,
+    hook_duration: HistogramFamily<HookLabels>,
+    extensions: Family<StatusLabels, Gauge>,
+}
+
+static METRICS: LazyLock<AgentMetrics> = LazyLock::new(AgentMetrics::new);
+
+pub fn metrics() -> &'static AgentMetrics {
+    &METRICS
+}
+
+impl AgentMetrics {
+    fn new() -> Self {
+        let mut registry = Registry::with_prefix("cloudapi_agent");
+
+        let reconcile_cycles = Family::<OutcomeLabels, Counter>::default();
+        registry.register("reconcile_cycles", "Poll and reconcile cycles by outcome", reconcile_cycles.clone());
+
+        let reconcile_duration = Histogram::new(exponential_buckets(0.1, 2.0, 14));
+        registry.register_with_unit("reconcile_duration", "Duration of poll and reconcile cycles", Unit::Seconds, reconcile_duration.clone());
+
+        let poll_failures = Counter::default();
+        registry.register("poll_failures", "Polls that could not reach any cloud-api endpoint", poll_failures.clone());
+
+        let downloads = Family::<DownloadLabels, Counter>::default();
+        registry.register("package_downloads", "Extension package downloads by result", downloads.clone());
+
+        let download_bytes = Counter::default();
+        registry.register_with_unit("package_download", "Bytes of extension packages downloaded", Unit::Bytes, download_bytes.clone());
+
+        let download_duration = Histogram::new(exponential_buckets(0.05, 2.0, 14));
+        registry.register_with_unit("package_download_duration", "Duration of extension package downloads", Unit::Seconds, download_duration.clone());
+
+        let hook_executions = Family::<HookExitLabels, Counter>::default();
+        registry.register("hook_executions", "Extension hook executions by extension, hook and exit code", hook_executions.clone());
+
+        let hook_duration: HistogramFamily<HookLabels> = Family::new_with_constructor(|| Histogram::new(exponential_buckets(0.5, 2.0, 14)));
+        registry.register_with_unit("hook_duration", "Duration of extension hook executions", Unit::Seconds, hook_duration.clone());
+
+        let extensions = Family::<StatusLabels, Gauge>::default();
+        registry.register("extensions", "Tracked extensions by status", extensions.clone());
+
+        AgentMetrics {
+            registry,
+            reconcile_cycles,
+            reconcile_duration,
+            poll_failures,
+            downloads,
+            download_bytes,
+            download_duration,
+            hook_executions,
+            hook_duration,
+            extensions,
+        }
+    }
+
+    pub fn record_reconcile(&self, outcome: PollOutcome, duration: Duration) {
+        let outcome_label = match outcome {
+            PollOutcome::Succeeded => "succeeded",
+            PollOutcome::PendingWork => "pending_work",
+            PollOutcome::EndpointUnreachable => "endpoint_unreachable",
+        };
+
+        self.reconcile_cycles.get_or_create(&OutcomeLabels { outcome: outcome_label.to_string() }).inc();
+        self.reconcile_duration.observe(duration.as_secs_f64());
+
+        if outcome == PollOutcome::EndpointUnreachable {
+            self.record_poll_failure();
+        }
+    }
+
+    pub fn record_poll_failure(&self) {
+        self.poll_failures.inc();
+    }
+
+    pub fn record_download(&self, result: DownloadResult, bytes: u64, duration: Duration) {
+        let result_label = match result {
+            DownloadResult::Downloaded => "downloaded",
+            DownloadResult::CacheHit => "cache_hit",
+            DownloadResult::Failed => "failed",
+        };
+
+        self.downloads.get_or_create(&DownloadLabels { result: result_label.to_string() }).inc();
+
+        if let DownloadResult::Downloaded = result {
+            self.download_bytes.inc_by(bytes);
+            self.download_duration.observe(duration.as_secs_f64());
+        }
+    }
+
+    pub fn record_hook(&self, extension: &str, hook: &str, exit: HookExit, duration: Duration) {
+        let exit_code = match exit {
+            HookExit::Code(code) => code.to_string(),
+            HookExit::TimedOut => "timeout".to_string(),
+            HookExit::Error => "error".to_string(),
+        };
+
+        self.hook_executions.get_or_create(&HookExitLabels {
+            extension: extension.to_string(),
+            hook: hook.to_string(),
+            exit_code,
+        }).inc();
+
+        self.hook_duration.get_or_create(&HookLabels {
+            extension: extension.to_string(),
+            hook: hook.to_string(),
+        }).observe(duration.as_secs_f64());
+    }
+
+    /**
+     * Sets the extension gauges from the state database. Every status is always reported
+     * so that a status dropping to zero shows as zero rather than disappearing.
+     */
+    pub fn set_extension_counts(&self, state_db: &StateDb) {
+        for status in EXTENSION_STATUSES {
+            let count = state_db.extensions.values().filter(|observed| observed.status == status).count();
+            self.extensions.get_or_create(&StatusLabels { status: format!("{:?}", status) }).set(count as i64);
+        }
+    }
+
+    pub fn encode(&self) -> Result<String, std::fmt::Error> {
+        let mut buffer = String::new();
+        prometheus_client::encoding::text::encode(&mut buffer, &self.registry)?;
+        Ok(buffer)
+    }
+}
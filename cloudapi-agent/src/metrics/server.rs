@@ -0,0 +1,78 @@
+use anyhow::{Context, Result};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/**
+ * Serves `GET /metrics` on the given address until cancelled. This is deliberately a
+ * minimal HTTP/1.1 responder: one request per connection, no keep-alive.
+ */
+pub async fn serve(listen_address: &str, cancellation_token: CancellationToken) -> Result<()> {
+    let listener = TcpListener::bind(listen_address).await
+        .context(format!("Failed to bind metrics endpoint: {}", listen_address))?;
+
+    tracing::info!("Metrics endpoint listening on http://{}/metrics", listener.local_addr()?);
+
+    loop {
+        let stream = tokio::select! {
+            _ = cancellation_token.cancelled() => break,
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    tracing::warn!("Failed to accept metrics connection: {:?}", e);
+                    continue;
+                }
+            },
+        };
+
+        tokio::spawn(async move {
+            if let Err(e) = tokio::time::timeout(REQUEST_TIMEOUT, handle_connection(stream)).await.unwrap_or_else(|_| Err(anyhow::anyhow!("Request timed out"))) {
+                tracing::debug!("Failed to serve metrics request: {:?}", e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+async fn handle_connection(stream: TcpStream) -> Result<()> {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+
+    // Skip the headers; nothing in them changes the response.
+    loop {
+        let mut header = String::new();
+
+        if reader.read_line(&mut header).await? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", CONTENT_TYPE, super::metrics().encode()?),
+        (Some("GET"), _) => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "text/plain", "Method not allowed\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+
+    let stream = reader.get_mut();
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
@@ -0,0 +1,194 @@
+use chrono::{DateTime, Utc};
+use cloudapi_sdk::client::CloudApiClient;
+use cloudapi_sdk::model::extension::ExtensionStatus;
+use cloudapi_sdk::model::heartbeat::{AgentHeartbeat, ExtensionSummary, OsInfo, ReconcileResult, ReconcileSummary};
+use cloudapi_sdk::model::hook::HookRunSummary;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::select;
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::{AgentConfig, HeartbeatConfig};
+use crate::extension::history;
+use crate::service::schedule::PollOutcome;
+use crate::state::StateDb;
+
+/**
+ * Tracks what the agent reports in its heartbeat. The poll loop hands it the connected
+ * client and the outcome of each reconciliation; `run` sends it on its own schedule.
+ */
+pub struct Heartbeat {
+    started_at: DateTime<Utc>,
+    started: Instant,
+    client: Mutex<Option<CloudApiClient>>,
+    last_reconcile: Mutex<Option<ReconcileSummary>>,
+}
+
+impl Heartbeat {
+    pub fn new() -> Self {
+        Heartbeat {
+            started_at: Utc::now(),
+            started: Instant::now(),
+            client: Mutex::new(None),
+            last_reconcile: Mutex::new(None),
+        }
+    }
+
+    pub fn set_client(&self, client: CloudApiClient) {
+        *self.client.lock().unwrap() = Some(client);
+    }
+
+    pub fn record_reconcile(&self, outcome: PollOutcome) {
+        let result = match outcome {
+            PollOutcome::EndpointUnreachable => ReconcileResult::EndpointUnreachable,
+            _ if has_failed_extensions() => ReconcileResult::Degraded,
+            _ => ReconcileResult::Succeeded,
+        };
+
+        *self.last_reconcile.lock().unwrap() = Some(ReconcileSummary {
+            completed_at: Utc::now(),
+            result,
+        });
+    }
+
+    /**
+     * Sends a heartbeat. A failed heartbeat is logged and retried on the next tick.
+     */
+    async fn send(&self, client: &CloudApiClient) {
+        let heartbeat = AgentHeartbeat {
+            agent_version: env!("CARGO_PKG_VERSION").to_string(),
+            sent_at: Utc::now(),
+            started_at: self.started_at,
+            uptime_secs: self.started.elapsed().as_secs(),
+            os: os_info(),
+            last_reconcile: self.last_reconcile.lock().unwrap().clone(),
+            extensions: extension_summaries(),
+        };
+
+        if let Err(e) = client.send_heartbeat(&heartbeat).await {
+            tracing::warn!("Failed to send heartbeat: {}", e);
+        }
+    }
+}
+
+/**
+ * Sends heartbeats every `heartbeat.interval_secs` until cancelled, independent of polling
+ * and reconciliation, so a long install hook or a backed-off poll does not make a healthy
+ * agent look NotReady. The config is re-read on every tick.
+ */
+pub async fn run(heartbeat: Arc<Heartbeat>, config_path: PathBuf, cancellation_token: CancellationToken) {
+    let mut interval_secs = HeartbeatConfig::default().interval_secs;
+    let mut interval = heartbeat_interval(interval_secs);
+
+    loop {
+        select! {
+            _ = cancellation_token.cancelled() => return,
+            _ = interval.tick() => {}
+        }
+
+        let config = match AgentConfig::load(&config_path) {
+            Ok(config) => config.heartbeat,
+            Err(e) => {
+                tracing::warn!("Failed to load config, using default heartbeat settings: {:?}", e);
+                HeartbeatConfig::default()
+            }
+        };
+
+        if config.interval_secs != interval_secs && config.interval_secs > 0 {
+            interval_secs = config.interval_secs;
+            interval = heartbeat_interval(interval_secs);
+            interval.reset();
+        }
+
+        let client = heartbeat.client.lock().unwrap().clone();
+
+        if let Some(client) = client.filter(|_| config.enabled) {
+            heartbeat.send(&client).await;
+        }
+    }
+}
+
+fn heartbeat_interval(interval_secs: u64) -> tokio::time::Interval {
+    let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    interval
+}
+
+fn load_state_db() -> Option<StateDb> {
+    match StateDb::load(&StateDb::default_path()) {
+        Ok(state_db) => Some(state_db),
+        Err(e) => {
+            tracing::warn!("Failed to load state database: {:?}", e);
+            None
+        }
+    }
+}
+
+fn has_failed_extensions() -> bool {
+    load_state_db().is_some_and(|state_db| {
+        state_db.extensions.values().any(|observed| observed.status == ExtensionStatus::Failed)
+    })
+}
+
+fn extension_summaries() -> Vec<ExtensionSummary> {
+    load_state_db()
+        .map(|state_db| {
+            state_db.extensions.into_values()
+                .map(|observed| ExtensionSummary {
+                    last_run: last_run_summary(&observed.package_id),
+                    package_id: observed.package_id,
+                    version: observed.version,
+                    status: observed.status,
+                    last_error: observed.last_error,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn last_run_summary(package_id: &str) -> Option<HookRunSummary> {
+    match history::last_run(package_id) {
+        Ok(run) => run.map(|run| run.summary()),
+        Err(e) => {
+            tracing::warn!("Failed to read run history of {}: {:?}", package_id, e);
+            None
+        }
+    }
+}
+
+fn os_info() -> OsInfo {
+    OsInfo {
+        family: std::env::consts::OS.to_string(),
+        arch: std::env::consts::ARCH.to_string(),
+        version: os_version(),
+        hostname: hostname(),
+    }
+}
+
+#[cfg(unix)]
+fn os_version() -> Option<String> {
+    let os_release = std::fs::read_to_string("/etc/os-release").ok()?;
+
+    os_release.lines()
+        .find_map(|line| line.strip_prefix("PRETTY_NAME="))
+        .map(|name| name.trim_matches('"').to_string())
+}
+
+#[cfg(windows)]
+fn os_version() -> Option<String> {
+    None
+}
+
+#[cfg(unix)]
+fn hostname() -> Option<String> {
+    std::fs::read_to_string("/etc/hostname").ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+#[cfg(windows)]
+fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
@@ -5,20 +5,38 @@
 // pub mod unix;
 use anyhow::{Context, Result};
 use chrono::Utc;
-use cloudapi_sdk::client::CloudApiClient;
-use cloudapi_sdk::model::extension::{ExtensionState, ExtensionStatus};
+use cloudapi_sdk::client::{CloudApiClient, CloudApiClientBuilder};
+use cloudapi_sdk::model::extension::{ExtensionList, ExtensionState, ExtensionStatus};
+use cloudapi_sdk::model::hook::RunTrigger;
+use cloudapi_sdk::model::resource::ResourceRef;
+use cloudapi_sdk::model::status::VirtualMachineStatus;
 use sha2::{Digest, Sha256};
-use tokio::{select, signal, process::Command};
+use tokio::{select, signal};
+use tokio::sync::Notify;
 use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 use std::{fs, path::Path, path::PathBuf};
 use std::fs::File;
 use std::io::BufReader;
-use crate::config::AgentConfig;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::config::{AgentConfig, HookConfig, PollConfig};
 use crate::constants;
-use crate::extension::ExtensionRunLog;
+use crate::control::{self, ControlHandle};
+use crate::metrics::{self, DownloadResult};
+use crate::state::{DesiredState, ObservedExtension, RuntimeStatus, StateDb};
+use crate::storage;
 use zip::ZipArchive;
 
-mod setup;
+mod heartbeat;
+pub mod plan;
+pub mod schedule;
+pub mod setup;
+pub mod update;
+mod watch;
+
+use heartbeat::Heartbeat;
+use schedule::{PollOutcome, PollSchedule};
 
 pub async fn run_service() -> Result<()> {
     // Setup cancellation token
@@ -29,20 +47,49 @@ pub async fn run_service() -> Result<()> {
     setup::create_application_data_dir(constants::DEFAULT_CLOUD_API_ROOT_DIR)?;
 
     // Example config file path
-    let config_file = format!("{}/agent.config.json", constants::DEFAULT_CLOUD_API_ROOT_DIR);
+    let config_file = format!("{}/{}", constants::DEFAULT_CLOUD_API_ROOT_DIR, constants::AGENT_CONFIG_FILE);
 
     // Ensure config file exists
     setup::create_default_config_file_if_missing(config_file.as_str())?;
 
+    if let Err(e) = crate::state::migrate_legacy_config(Path::new(&config_file), &DesiredState::default_path(), &StateDb::default_path()) {
+        tracing::error!("Failed to migrate extensions out of the agent config: {:?}", e);
+    }
+
+    let control_handle = ControlHandle::default();
+
+    // Spawn local control API
+    let control_token = cancel_token.clone();
+    let control_server_handle = control_handle.clone();
+    tokio::spawn(async move {
+        if let Err(e) = control::server::serve(control_server_handle, control_token).await {
+            tracing::error!("Control API stopped: {:?}", e);
+        }
+    });
+
+    // Spawn metrics endpoint
+    match AgentConfig::load(Path::new(&config_file)) {
+        Ok(config) if config.metrics.enabled => {
+            let metrics_token = cancel_token.clone();
+            tokio::spawn(async move {
+                if let Err(e) = metrics::server::serve(&config.metrics.listen_address, metrics_token).await {
+                    tracing::error!("Metrics endpoint stopped: {:?}", e);
+                }
+            });
+        }
+        Ok(_) => {}
+        Err(e) => tracing::warn!("Failed to load config, metrics endpoint not started: {:?}", e),
+    }
+
     // Spawn main polling task
     let poll_task = tokio::spawn(async move {
-        poll_and_reconcile_config(config_file.as_str(), 15, cancel_token).await
+        poll_and_reconcile_config(config_file.as_str(), control_handle, cancel_token).await
     });
 
     // Spawn signal handler
     let shutdown_task = tokio::spawn(async move {
         wait_for_shutdown_signal().await?;
-        println!("[shutdown] Signal received. Triggering shutdown...");
+        tracing::info!("Signal received, shutting down.");
         shutdown_token.cancel();
         Ok::<_, anyhow::Error>(())
     });
@@ -50,10 +97,10 @@ pub async fn run_service() -> Result<()> {
     // Wait for either task to complete
     select! {
         res = poll_task => {
-            println!("[service] Polling task completed: {:?}", res);
+            tracing::info!("Polling task completed: {:?}", res);
         },
         res = shutdown_task => {
-            println!("[service] Shutdown handler completed: {:?}", res);
+            tracing::info!("Shutdown handler completed: {:?}", res);
         }
     }
 
@@ -68,10 +115,10 @@ pub async fn wait_for_shutdown_signal() -> Result<()> {
 
         select! {
             _ = sigterm.recv() => {
-                println!("[signal] Received SIGTERM");
+                tracing::info!("Received SIGTERM");
             },
             _ = sigint.recv() => {
-                println!("[signal] Received SIGINT (Ctrl+C)");
+                tracing::info!("Received SIGINT");
             },
         }
     }
@@ -79,135 +126,378 @@ pub async fn wait_for_shutdown_signal() -> Result<()> {
     #[cfg(windows)]
     {
         signal::ctrl_c().await?;
-        println!("[signal] Received Ctrl+C (Windows)");
+        tracing::info!("Received Ctrl+C");
     }
 
     Ok(())
 }
 
-async fn pull_latest_extension_states(config: &AgentConfig) -> Result<Vec<ExtensionState>> {
-    CloudApiClient::new(config.get_cloudapi_endpoint())
-        .get_extensions()
+/**
+ * Builds a client for the configured endpoints, starting on the endpoint that was last
+ * healthy. The client is not registered, so it only reaches the metadata endpoints.
+ */
+fn build_client(config: &AgentConfig) -> Result<CloudApiClient> {
+    let mut builder = CloudApiClientBuilder::with_endpoints(config.get_cloudapi_endpoints());
+
+    match StateDb::load(&StateDb::default_path()) {
+        Ok(state_db) => {
+            if let Some(endpoint) = state_db.last_healthy_endpoint {
+                builder = builder.preferred_endpoint(endpoint);
+            }
+        }
+        Err(e) => tracing::warn!("Failed to load state database: {:?}", e),
+    }
+
+    Ok(builder.build()?)
+}
+
+/**
+ * Builds a client with `build_client` and probes the list so the first request goes to an endpoint that answers.
+ * The client then registers to obtain credentials; a failed registration is retried by
+ * the client on the next call.
+ */
+async fn connect_client(config: &AgentConfig) -> Result<CloudApiClient> {
+    let client = build_client(config)?;
+
+    match client.probe().await {
+        Ok(endpoint) => tracing::info!("Using cloud-api endpoint: {}", endpoint),
+        Err(e) => tracing::warn!("No cloud-api endpoint is reachable, will keep trying {}: {}", client.active_endpoint(), e),
+    }
+
+    match client.register(config.get_registration()).await {
+        Ok(credential) => tracing::info!("Registered as {} (credential expires {})", credential.resource, credential.expires_at),
+        Err(e) => tracing::warn!("Failed to register with the server: {}", e),
+    }
+
+    Ok(client)
+}
+
+/**
+ * Reports the observed state of every assigned extension to the server.
+ */
+async fn report_status(client: &CloudApiClient, desired_state: &DesiredState, state_db: &StateDb) {
+    let extensions = desired_state.get_extensions().iter()
+        .map(|desired| {
+            let mut reported = desired.clone();
+
+            match state_db.get(&desired.uid) {
+                Some(observed) => {
+                    reported.version = observed.version.clone();
+                    reported.status = observed.status.clone();
+                    reported.modified_at = observed.updated_at.clone();
+                }
+                None => reported.status = ExtensionStatus::NotInstalled,
+            }
+
+            reported
+        })
+        .collect();
+
+    let status = VirtualMachineStatus {
+        reported_at: Some(Utc::now().to_rfc3339()),
+        extensions,
+        agent: None,
+    };
+
+    if let Err(e) = client.report_status(&status).await {
+        tracing::warn!("Failed to report status: {}", e);
+    }
+}
+
+fn get_package_endpoint(cloudapi_endpoint: &str) -> String {
+    format!("{}/{}", cloudapi_endpoint, "package")
+}
+
+async fn pull_latest_extension_states(client: &CloudApiClient) -> Result<ExtensionList> {
+    client
+        .poll_extensions()
         .await
         .context("Failed to pull latest extension data")
 }
 
-async fn poll_and_reconcile_config(path: &str, interval_secs: u64, cancellation_token: CancellationToken) -> Result<()> {
+/// Endpoints and registered resource a client was built for; a change rebuilds the client.
+type ClientKey = (Vec<String>, Option<ResourceRef>);
+
+async fn poll_and_reconcile_config(path: &str, control_handle: ControlHandle, cancellation_token: CancellationToken) -> Result<()> {
     let path = Path::new(path).to_path_buf();
-    let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+    let mut schedule = PollSchedule::default();
+    let mut delay = Duration::ZERO;
+    let mut client: Option<(ClientKey, CloudApiClient)> = None;
+    let mut user_data_done = false;
+    let heartbeat = Arc::new(Heartbeat::new());
+    tokio::spawn(heartbeat::run(heartbeat.clone(), path.clone(), cancellation_token.clone()));
+    let started_at = Utc::now().to_rfc3339();
+
+    let config_changed = Arc::new(Notify::new());
+    let _config_watcher = match watch::watch_config(&path, config_changed.clone()) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            tracing::warn!("Failed to watch config file, changes are picked up on the next poll: {:?}", e);
+            None
+        }
+    };
 
     loop {
-        if cancellation_token.is_cancelled() {
-            tracing::info!("Cancellation token triggered. Exiting poll loop.");
-            return Ok(());
+        select! {
+            _ = cancellation_token.cancelled() => {
+                tracing::info!("Cancellation token triggered. Exiting poll loop.");
+                return Ok(());
+            }
+            _ = tokio::time::sleep(delay) => {}
+            _ = control_handle.reconcile_now.notified() => {
+                tracing::info!("Reconciliation requested through the control API.");
+            }
+            _ = config_changed.notified() => {
+                tracing::info!("Config changed. Reconciling now.");
+            }
         }
 
-        interval.tick().await;
-
-        match std::fs::read_to_string(&path) {
-            Ok(contents) => {
-                match serde_json::from_str::<AgentConfig>(&contents) {
-                    Ok(config) => {
-                        tracing::info!("Reloaded config. Starting reconciliation...");
-                        let mut config = config;
-
-                        let extension_states = pull_latest_extension_states(&config)
-                            .await;
-
-                        if extension_states.is_ok() {
-                            tracing::info!("Updating config with latest extension states...");
-                            
-                            for state in extension_states.unwrap() {
-                                config.remove_extension(state.uid.as_str());
-                                config.add_extension(state);
-                            }
-
-                            // Save updated config
-                            let updated_config = serde_json::to_string_pretty(&config)?;
-                            fs::write(&path, updated_config)?;
-                            tracing::info!("Updated config file with latest extension states.");
-                        }
-                        else
-                        {
-                            tracing::warn!("Failed to pull latest extension states: {:?}", extension_states);
-                        }
-
-                        reconcile_extensions(&config).await?;
-                    }
-                    Err(e) => {
-                        tracing::error!("Failed to parse config file: {:?}", e);
-                    }
-                }
-            }
+        let config = match AgentConfig::load(&path) {
+            Ok(config) => config,
             Err(e) => {
-                tracing::error!("Failed to read config file: {:?}", e);
+                tracing::error!("Failed to load config file: {:?}", e);
+                delay = Duration::from_secs(PollConfig::default().interval_secs);
+                continue;
+            }
+        };
+
+        let client_key: ClientKey = (config.get_cloudapi_endpoints(), config.get_registration().resource);
+
+        let client = match client.as_ref().filter(|(key, _)| *key == client_key) {
+            Some((_, client)) => client,
+            None => match connect_client(&config).await {
+                Ok(connected) => {
+                    heartbeat.set_client(connected.clone());
+                    &client.insert((client_key, connected)).1
+                }
+                Err(e) => {
+                    tracing::error!("Failed to create cloud-api client: {:?}", e);
+                    metrics::metrics().record_poll_failure();
+                    delay = Duration::from_secs(config.get_poll_config().interval_secs);
+                    continue;
+                }
+            },
+        };
+
+        if config.user_data.enabled && !user_data_done {
+            match crate::user_data::run_once(client, &config.user_data).await {
+                Ok(()) => user_data_done = true,
+                Err(e) => tracing::warn!("Failed to run user-data, will retry: {:?}", e),
             }
         }
+
+        let reconcile_started = Instant::now();
+        let outcome = {
+            let _guard = control_handle.reconcile_lock.lock().await;
+            poll_and_reconcile_once(&config, client).await
+        };
+        metrics::metrics().record_reconcile(outcome, reconcile_started.elapsed());
+
+        heartbeat.record_reconcile(outcome);
+
+        if let Err(e) = update::check_health(outcome).await {
+            tracing::error!("Failed to check agent update health: {:?}", e);
+        }
+
+        schedule.record(outcome);
+        delay = schedule.next_delay(config.get_poll_config(), outcome);
+        tracing::info!("Next poll in {:?} ({:?}).", delay, outcome);
+
+        let runtime_status = RuntimeStatus {
+            pid: std::process::id(),
+            agent_version: env!("CARGO_PKG_VERSION").to_string(),
+            started_at: started_at.clone(),
+            updated_at: Utc::now().to_rfc3339(),
+            active_endpoint: Some(client.active_endpoint().to_string()),
+            last_outcome: Some(format!("{:?}", outcome)),
+            next_poll_at: chrono::Duration::from_std(delay).ok().map(|delay| (Utc::now() + delay).to_rfc3339()),
+        };
+
+        if let Err(e) = runtime_status.save(&RuntimeStatus::default_path()) {
+            tracing::warn!("Failed to write runtime status: {:?}", e);
+        }
+
+        *control_handle.runtime_status.lock().unwrap() = Some(runtime_status);
     }
 }
 
-async fn reconcile_extensions(config: &AgentConfig) -> Result<()> {
-    for extension in config.get_extensions() {
-        tracing::info!(">> Reconciling extension: {} (version: {})", extension.get_package_id(), extension.version);
+/**
+ * Runs a single poll and reconciliation cycle from this process, for `reconcile --once`.
+ */
+pub async fn reconcile_once() -> Result<()> {
+    let _instance_lock = crate::instance::InstanceLock::acquire(&crate::instance::InstanceLock::default_path())
+        .context("Cannot reconcile from this process; use `reconcile` to ask the running agent")?;
+    let config_file = Path::new(constants::DEFAULT_CLOUD_API_ROOT_DIR).join(constants::AGENT_CONFIG_FILE);
+    let config = AgentConfig::load(&config_file)?;
+    let client = connect_client(&config).await?;
+
+    let outcome = poll_and_reconcile_once(&config, &client).await;
+    tracing::info!("Reconciliation finished ({:?}).", outcome);
+
+    if outcome == PollOutcome::EndpointUnreachable {
+        return Err(anyhow::anyhow!("The cloud-api endpoint was unreachable; reconciled against the cached desired state"));
+    }
 
-        if needs_update(extension).await? {
-            tracing::info!("Extension {} needs update or install.", extension.get_package_id());
-            let result = install_or_update_extension(extension, config.get_package_endpoint().as_str(), config.get_package_cache()).await;
-            
-            if let Err(e) = result {
-                tracing::error!("Failed to install/update extension {}: {:?}", extension.get_package_id(), e);
+    Ok(())
+}
+
+/**
+ * Computes what a reconciliation would do against the latest assignments from the server,
+ * falling back to the cached desired state, without downloading or executing anything.
+ */
+pub async fn plan_once() -> Result<plan::ReconcilePlan> {
+    let config_file = Path::new(constants::DEFAULT_CLOUD_API_ROOT_DIR).join(constants::AGENT_CONFIG_FILE);
+    let config = AgentConfig::load(&config_file)?;
+
+    // Planning must not change anything on the server, which registering would.
+    let client = build_client(&config)?;
+
+    let desired_state = match pull_latest_extension_states(&client).await {
+        Ok(extension_list) => DesiredState::new(extension_list.extensions),
+        Err(e) => {
+            tracing::warn!("Failed to pull latest extension states, planning against cached desired state: {:?}", e);
+            DesiredState::load(&DesiredState::default_path())?
+        }
+    };
+
+    plan::plan_extensions(&desired_state, &StateDb::load(&StateDb::default_path())?)
+}
+
+/**
+ * Runs one poll and reconciliation cycle. Failures are logged rather than returned so
+ * that nothing short of cancellation terminates the poll loop.
+ */
+async fn poll_and_reconcile_once(config: &AgentConfig, client: &CloudApiClient) -> PollOutcome {
+    let desired_state_path = DesiredState::default_path();
+    let state_db_path = StateDb::default_path();
+
+    tracing::info!("Reloaded config. Starting reconciliation...");
+
+    let (desired_state, outcome) = match pull_latest_extension_states(client).await {
+        Ok(extension_list) => {
+            let desired_state = DesiredState::new(extension_list.extensions);
+
+            if let Err(e) = desired_state.save(&desired_state_path) {
+                tracing::error!("Failed to update desired state cache: {:?}", e);
             } else {
-                tracing::info!("Extension {} installed/updated successfully.", extension.get_package_id());
+                tracing::info!("Updated desired state cache with latest extension states.");
+            }
+
+            let outcome = if extension_list.pending_work { PollOutcome::PendingWork } else { PollOutcome::Succeeded };
+            (desired_state, outcome)
+        }
+        Err(e) => {
+            tracing::warn!("Failed to pull latest extension states, using cached desired state: {:?}", e);
+
+            match DesiredState::load(&desired_state_path) {
+                Ok(desired_state) => (desired_state, PollOutcome::EndpointUnreachable),
+                Err(e) => {
+                    tracing::error!("Failed to load cached desired state: {:?}", e);
+                    return PollOutcome::EndpointUnreachable;
+                }
             }
         }
+    };
+
+    let mut state_db = match StateDb::load(&state_db_path) {
+        Ok(state_db) => state_db,
+        Err(e) => {
+            tracing::error!("Failed to load state database: {:?}", e);
+            return outcome;
+        }
+    };
+
+    if outcome != PollOutcome::EndpointUnreachable {
+        state_db.last_healthy_endpoint = Some(client.active_endpoint().to_string());
     }
 
-    crate::extension::uninstall::uninstall_extensions(config).await?;
+    let package_endpoint = get_package_endpoint(client.active_endpoint());
+    reconcile_extensions(config, &package_endpoint, &desired_state, &mut state_db).await;
 
-    tracing::info!("Reconciliation complete.");
+    report_status(client, &desired_state, &state_db).await;
 
-    Ok(())
+    if outcome != PollOutcome::EndpointUnreachable {
+        if let Err(e) = crate::extension::events::forward_scheduled_events(client, &mut state_db, &config.hooks).await {
+            tracing::warn!("Failed to forward scheduled events: {:?}", e);
+        }
+    }
+
+    if let Err(e) = state_db.save(&state_db_path) {
+        tracing::error!("Failed to save state database: {:?}", e);
+    }
+
+    metrics::metrics().set_extension_counts(&state_db);
+
+    outcome
 }
 
+async fn reconcile_extensions(config: &AgentConfig, package_endpoint: &str, desired_state: &DesiredState, state_db: &mut StateDb) {
+    for extension in desired_state.get_extensions() {
+        let span = tracing::info_span!("extension", id = %extension.get_package_id(), version = %extension.version);
+        reconcile_extension(config, package_endpoint, extension, state_db).instrument(span).await;
+    }
+
+    if let Err(e) = crate::extension::uninstall::uninstall_extensions(desired_state, state_db, &config.hooks).await {
+        tracing::error!("Failed to uninstall extensions: {:?}", e);
+    }
 
+    tracing::info!("Reconciliation complete.");
+}
 
-async fn needs_update(extension: &ExtensionState) -> Result<bool> {   
-    match extension.status {
-        ExtensionStatus::Uninstalling => {
-            tracing::info!("Extension {} is currently uninstalling.", extension.get_package_id());
-            return Ok(false);
-        }
-        ExtensionStatus::Uninstalled => {
-            tracing::info!("Extension {} is currently uninstalled.", extension.get_package_id());
-            return Ok(false);
-        }
-        ExtensionStatus::Disabled => {
-            tracing::info!("Extension {} is currently disabled.", extension.get_package_id());
-            return Ok(false);
+async fn reconcile_extension(config: &AgentConfig, package_endpoint: &str, extension: &ExtensionState, state_db: &mut StateDb) {
+    tracing::info!("Reconciling extension.");
+
+    if update::is_agent_extension(extension) {
+        if let Err(e) = update::reconcile_agent_update(extension, package_endpoint, config.get_package_cache(), state_db).await {
+            tracing::error!("Failed to update agent to {}: {:?}", extension.version, e);
+            let mut observed = ObservedExtension::new(extension, ExtensionStatus::Failed);
+            observed.last_error = Some(format!("{:#}", e));
+            state_db.upsert(observed);
         }
-        _ => {}
+
+        return;
     }
 
-    let extension_dir = format!("C:\\cloud-api\\extensions\\{}", extension.get_package_id());
-    let version_file = PathBuf::from(&extension_dir).join("VERSION");
+    let needs_update = match needs_update(extension, state_db).await {
+        Ok(needs_update) => needs_update,
+        Err(e) => {
+            tracing::error!("Failed to determine state of extension {}: {:?}", extension.get_package_id(), e);
+            return;
+        }
+    };
 
-    if !version_file.exists() {
-        tracing::info!("Extension {} not installed.", extension.get_package_id());
-        return Ok(true);
-    }
+    if needs_update {
+        tracing::info!("Extension {} needs update or install.", extension.get_package_id());
+        let result = install_or_update_extension(extension, package_endpoint, config.get_package_cache(), &config.hooks).await;
+        let mut observed = ObservedExtension::new(extension, ExtensionStatus::Installed);
 
-    let current_version_hash = fs::read_to_string(version_file)?.trim().to_string();
-    let version_hash = hash_extension_state(extension)?;
+        match result {
+            Ok(version_hash) => {
+                tracing::info!("Extension {} installed/updated successfully.", extension.get_package_id());
+                observed.version_hash = Some(version_hash);
+            }
+            Err(e) => {
+                tracing::error!("Failed to install/update extension {}: {:?}", extension.get_package_id(), e);
+                observed.status = ExtensionStatus::Failed;
+                observed.last_error = Some(format!("{:#}", e));
+            }
+        }
 
-    if current_version_hash != version_hash {
-        tracing::info!("Extension {} version mismatch: current {}, desired {}", extension.get_package_id(), current_version_hash, version_hash);
-        return Ok(true);
+        state_db.upsert(observed);
     }
+}
 
-    Ok(false)
+async fn needs_update(extension: &ExtensionState, state_db: &StateDb) -> Result<bool> {
+    match plan::plan_install(extension, state_db)? {
+        Some(step) => {
+            tracing::info!("Extension {} needs {:?}: {}", extension.get_package_id(), step.action, step.reason);
+            Ok(true)
+        }
+        None => Ok(false),
+    }
 }
 
-pub async fn install_or_update_extension(extension: &ExtensionState, endpoint: &str, cache_dir: &str) -> Result<()> {
+pub async fn install_or_update_extension(extension: &ExtensionState, endpoint: &str, cache_dir: &str, hook_config: &HookConfig) -> Result<String> {
     let extension_pkg = format!("{}-{}.extpkg", extension.get_package_id(), extension.version);
     let package_path = download_package(endpoint, &extension_pkg, cache_dir).await?;
 
@@ -220,37 +510,8 @@ pub async fn install_or_update_extension(extension: &ExtensionState, endpoint: &
 
     extract_package(&package_path, &target_dir).await?;
 
-    // === One-off PowerShell execution ===
-    let ps_script = Path::new(&target_dir).join("install.ps1");
-    let ran_marker = Path::new(&target_dir).join("ran.lock");
-
-    if ps_script.exists() && !ran_marker.exists() {
-        let output = Command::new("pwsh")
-            .arg("-NoProfile")
-            .arg("-ExecutionPolicy").arg("Bypass")
-            .arg("-File").arg(ps_script)
-            .output()
-            .await
-            .context("Failed to execute PowerShell script")?;
-
-        let log = ExtensionRunLog {
-            executed_at: Utc::now().to_rfc3339(),
-            exit_code: output.status.code().unwrap_or(-1),
-            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
-            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
-        };
-
-        // Save run-log.json
-        let log_path = Path::new(&target_dir).join("run-log.json");
-        fs::write(&log_path, serde_json::to_vec_pretty(&log)?)?;
-
-        // Mark script as executed
-        fs::write(&ran_marker, log.executed_at.as_bytes())?;
-
-        // Optional: error if script failed
-        if log.exit_code != 0 {
-            return Err(anyhow::anyhow!("PowerShell script failed with code {}", log.exit_code));
-        }
+    if !crate::extension::install::install_script_ran(Path::new(&target_dir)) {
+        crate::extension::install::run_install_script(&extension.get_package_id(), &extension.version, RunTrigger::Reconcile, hook_config).await?;
     }
 
     // Write version marker
@@ -262,9 +523,9 @@ pub async fn install_or_update_extension(extension: &ExtensionState, endpoint: &
 
     let version_hash = hash_extension_state(extension)?;
 
-    fs::write(&version_path, version_hash)?;
+    fs::write(&version_path, &version_hash)?;
 
-    Ok(())
+    Ok(version_hash)
 }
 
 async fn download_package(endpoint: &str, package_name: &str, cache_dir: &str) -> Result<PathBuf> {
@@ -275,15 +536,34 @@ async fn download_package(endpoint: &str, package_name: &str, cache_dir: &str) -
 
     if dest_path.exists() {
         tracing::info!("Package already downloaded: {:?}", dest_path);
+        metrics::metrics().record_download(DownloadResult::CacheHit, 0, Duration::ZERO);
         return Ok(dest_path);
     }
 
-    let client = reqwest::Client::new();
-    let response = client.get(&url).send().await?;
-    let bytes = response.bytes().await?;
+    let started = Instant::now();
+
+    // An error page must not end up in the cache, where it would be taken for the package.
+    let response: Result<_> = async {
+        let client = reqwest::Client::new();
+        let response = client.get(&url).send().await?
+            .error_for_status()
+            .context(format!("Failed to download package: {}", url))?;
+
+        Ok(response.bytes().await?)
+    }.await;
+
+    let bytes = match response {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            metrics::metrics().record_download(DownloadResult::Failed, 0, started.elapsed());
+            return Err(e);
+        }
+    };
+
+    metrics::metrics().record_download(DownloadResult::Downloaded, bytes.len() as u64, started.elapsed());
 
     fs::create_dir_all(cache_dir)?;
-    fs::write(&dest_path, &bytes)?;
+    storage::write_atomic(&dest_path, &bytes)?;
 
     Ok(dest_path)
 }
@@ -311,7 +591,7 @@ async fn extract_package(zip_path: &PathBuf, dest_dir: &str) -> Result<()> {
     Ok(())
 }
 
-fn hash_extension_state(spec: &ExtensionState) -> Result<String> {
+pub fn hash_extension_state(spec: &ExtensionState) -> Result<String> {
     // Canonical JSON serialization
     let json = serde_json::to_string(spec)?;
 
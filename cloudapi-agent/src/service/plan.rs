@@ -0,0 +1,265 @@
+use anyhow::Result;
+use cloudapi_sdk::model::extension::{ExtensionState, ExtensionStatus};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::constants;
+use crate::extension::uninstall::{find_orphaned_extensions, find_stale_extensions};
+use crate::service::{hash_extension_state, update};
+use crate::state::{DesiredState, StateDb};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlannedAction {
+    Install,
+    Upgrade,
+    Reinstall,
+    Uninstall,
+    RemoveStale,
+    RemoveOrphan,
+    UpdateAgent,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlannedStep {
+    pub action: PlannedAction,
+    pub package_id: String,
+    pub version: Option<String>,
+    pub reason: String,
+}
+
+/**
+ * What a reconciliation against `desired_state` would do, in the order it would do it.
+ */
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReconcilePlan {
+    pub desired_state_fetched_at: Option<String>,
+    pub steps: Vec<PlannedStep>,
+}
+
+/**
+ * Computes the reconciliation plan without downloading or executing anything. It makes
+ * the same decisions as `reconcile_extensions` and `uninstall_extensions`.
+ */
+pub fn plan_extensions(desired_state: &DesiredState, state_db: &StateDb) -> Result<ReconcilePlan> {
+    let mut steps = vec![];
+
+    for extension in desired_state.get_extensions() {
+        if update::is_agent_extension(extension) {
+            if let Some(reason) = update::update_reason(extension)? {
+                steps.push(step(PlannedAction::UpdateAgent, extension, reason));
+            }
+
+            continue;
+        }
+
+        if let Some(planned) = plan_install(extension, state_db)? {
+            steps.push(planned);
+        }
+    }
+
+    for extension in desired_state.get_extensions() {
+        if extension.status == ExtensionStatus::Uninstalling {
+            steps.push(step(PlannedAction::Uninstall, extension, "assigned as uninstalling".to_string()));
+        }
+    }
+
+    for observed in find_stale_extensions(desired_state, state_db) {
+        steps.push(PlannedStep {
+            action: PlannedAction::RemoveStale,
+            package_id: observed.package_id,
+            version: Some(observed.version),
+            reason: "no longer assigned".to_string(),
+        });
+    }
+
+    match find_orphaned_extensions(desired_state, state_db) {
+        Ok(orphans) => {
+            for package_id in orphans {
+                steps.push(PlannedStep {
+                    action: PlannedAction::RemoveOrphan,
+                    package_id,
+                    version: None,
+                    reason: "extension directory is neither assigned nor tracked".to_string(),
+                });
+            }
+        }
+        Err(e) => tracing::warn!("Failed to list extension directories: {:?}", e),
+    }
+
+    Ok(ReconcilePlan {
+        desired_state_fetched_at: desired_state.fetched_at.clone(),
+        steps,
+    })
+}
+
+/**
+ * Decides whether an assigned extension has to be installed, and why.
+ */
+pub fn plan_install(extension: &ExtensionState, state_db: &StateDb) -> Result<Option<PlannedStep>> {
+    let extensions_dir = format!("{}\\extensions", constants::DEFAULT_CLOUD_API_ROOT_DIR);
+    plan_install_in(&extensions_dir, extension, state_db)
+}
+
+fn plan_install_in(extensions_dir: &str, extension: &ExtensionState, state_db: &StateDb) -> Result<Option<PlannedStep>> {
+    if matches!(extension.status, ExtensionStatus::Uninstalling | ExtensionStatus::Uninstalled | ExtensionStatus::Disabled) {
+        return Ok(None);
+    }
+
+    let version_hash = hash_extension_state(extension)?;
+
+    if let Some(observed) = state_db.get(&extension.uid) {
+        if observed.status == ExtensionStatus::Installed && observed.version_hash.as_deref() == Some(version_hash.as_str()) {
+            return Ok(None);
+        }
+
+        let planned = if observed.version != extension.version {
+            step(PlannedAction::Upgrade, extension, format!("installed version {}, assigned {}", observed.version, extension.version))
+        } else if observed.status == ExtensionStatus::Failed {
+            step(PlannedAction::Reinstall, extension, format!("previous attempt failed: {}", observed.last_error.as_deref().unwrap_or("unknown error")))
+        } else if observed.status != ExtensionStatus::Installed {
+            step(PlannedAction::Install, extension, format!("observed as {:?}", observed.status))
+        } else {
+            step(PlannedAction::Reinstall, extension, format!("hash mismatch: installed {}, assigned {}", observed.version_hash.as_deref().unwrap_or("none"), version_hash))
+        };
+
+        return Ok(Some(planned));
+    }
+
+    // Installs made before the state database existed only have the VERSION marker.
+    let extension_dir = format!("{}\\{}", extensions_dir, extension.get_package_id());
+    let version_file = PathBuf::from(&extension_dir).join("VERSION");
+
+    if !version_file.exists() {
+        return Ok(Some(step(PlannedAction::Install, extension, "not installed".to_string())));
+    }
+
+    let current_version_hash = fs::read_to_string(version_file)?.trim().to_string();
+
+    if current_version_hash != version_hash {
+        return Ok(Some(step(PlannedAction::Upgrade, extension, format!("hash mismatch: installed {}, assigned {}", current_version_hash, version_hash))));
+    }
+
+    Ok(None)
+}
+
+fn step(action: PlannedAction, extension: &ExtensionState, reason: String) -> PlannedStep {
+    PlannedStep {
+        action,
+        package_id: extension.get_package_id(),
+        version: Some(extension.version.clone()),
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::ObservedExtension;
+
+    fn extension(version: &str) -> ExtensionState {
+        let mut extension = ExtensionState::new("uid-1", "sample", version);
+        extension.set_publisher("test");
+        extension
+    }
+
+    fn observed(extension: &ExtensionState, status: ExtensionStatus, version_hash: Option<String>) -> StateDb {
+        let mut observed = ObservedExtension::new(extension, status);
+        observed.version_hash = version_hash;
+
+        let mut state_db = StateDb::default();
+        state_db.upsert(observed);
+        state_db
+    }
+
+    fn plan(extension: &ExtensionState, state_db: &StateDb) -> Option<PlannedStep> {
+        let dir = tempfile::tempdir().unwrap();
+        plan_install_in(&dir.path().to_string_lossy(), extension, state_db).unwrap()
+    }
+
+    #[test]
+    fn an_unknown_extension_is_installed() {
+        let planned = plan(&extension("1.0.0"), &StateDb::default()).unwrap();
+
+        assert_eq!(planned.action, PlannedAction::Install);
+        assert_eq!(planned.reason, "not installed");
+    }
+
+    #[test]
+    fn an_installed_extension_with_a_matching_hash_is_left_alone() {
+        let assigned = extension("1.0.0");
+        let state_db = observed(&assigned, ExtensionStatus::Installed, Some(hash_extension_state(&assigned).unwrap()));
+
+        assert!(plan(&assigned, &state_db).is_none());
+    }
+
+    #[test]
+    fn a_new_version_is_an_upgrade() {
+        let state_db = observed(&extension("1.0.0"), ExtensionStatus::Installed, Some("old".to_string()));
+
+        let planned = plan(&extension("2.0.0"), &state_db).unwrap();
+
+        assert_eq!(planned.action, PlannedAction::Upgrade);
+        assert_eq!(planned.reason, "installed version 1.0.0, assigned 2.0.0");
+    }
+
+    #[test]
+    fn a_failed_install_is_retried() {
+        let assigned = extension("1.0.0");
+        let mut state_db = observed(&assigned, ExtensionStatus::Failed, None);
+        state_db.extensions.get_mut("uid-1").unwrap().last_error = Some("exit code 1".to_string());
+
+        let planned = plan(&assigned, &state_db).unwrap();
+
+        assert_eq!(planned.action, PlannedAction::Reinstall);
+        assert_eq!(planned.reason, "previous attempt failed: exit code 1");
+    }
+
+    #[test]
+    fn a_changed_assignment_of_the_same_version_is_reinstalled() {
+        let assigned = extension("1.0.0");
+        let state_db = observed(&assigned, ExtensionStatus::Installed, Some("stale".to_string()));
+
+        assert_eq!(plan(&assigned, &state_db).unwrap().action, PlannedAction::Reinstall);
+    }
+
+    #[test]
+    fn uninstalling_and_disabled_extensions_are_not_installed() {
+        for status in [ExtensionStatus::Uninstalling, ExtensionStatus::Uninstalled, ExtensionStatus::Disabled] {
+            let mut assigned = extension("1.0.0");
+            assigned.set_status(status);
+
+            assert!(plan(&assigned, &StateDb::default()).is_none());
+        }
+    }
+
+    fn legacy_install(extension: &ExtensionState, version_hash: &str) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let extension_dir = format!("{}\\{}", dir.path().to_string_lossy(), extension.get_package_id());
+        fs::create_dir_all(&extension_dir).unwrap();
+        fs::write(PathBuf::from(&extension_dir).join("VERSION"), format!("{}\n", version_hash)).unwrap();
+        dir
+    }
+
+    #[test]
+    fn a_legacy_install_with_a_matching_version_marker_is_left_alone() {
+        let assigned = extension("1.0.0");
+        let dir = legacy_install(&assigned, &hash_extension_state(&assigned).unwrap());
+
+        let planned = plan_install_in(&dir.path().to_string_lossy(), &assigned, &StateDb::default()).unwrap();
+
+        assert!(planned.is_none());
+    }
+
+    #[test]
+    fn a_legacy_install_with_a_different_version_marker_is_upgraded() {
+        let assigned = extension("2.0.0");
+        let dir = legacy_install(&assigned, "old");
+
+        let planned = plan_install_in(&dir.path().to_string_lossy(), &assigned, &StateDb::default()).unwrap().unwrap();
+
+        assert_eq!(planned.action, PlannedAction::Upgrade);
+        assert!(planned.reason.starts_with("hash mismatch: installed old, assigned "));
+    }
+}
@@ -0,0 +1,134 @@
+use rand::Rng;
+use std::time::Duration;
+
+use crate::config::PollConfig;
+
+/**
+ * Outcome of a single poll of the endpoint, used to decide when to poll next.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PollOutcome {
+    Succeeded,
+    PendingWork,
+    EndpointUnreachable,
+}
+
+/**
+ * Tracks consecutive poll failures and computes the delay before the next poll.
+ */
+#[derive(Debug, Default)]
+pub struct PollSchedule {
+    consecutive_failures: u32,
+}
+
+impl PollSchedule {
+    pub fn record(&mut self, outcome: PollOutcome) {
+        match outcome {
+            PollOutcome::EndpointUnreachable => {
+                self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+            }
+            _ => self.consecutive_failures = 0,
+        }
+    }
+
+    pub fn next_delay(&self, config: &PollConfig, outcome: PollOutcome) -> Duration {
+        let base_secs = match outcome {
+            PollOutcome::PendingWork => return Duration::from_secs(config.pending_work_interval_secs),
+            PollOutcome::Succeeded => config.interval_secs,
+            PollOutcome::EndpointUnreachable => {
+                let exponent = self.consecutive_failures.saturating_sub(1).min(16);
+                config.interval_secs
+                    .saturating_mul(1u64 << exponent)
+                    .min(config.max_backoff_secs.max(config.interval_secs))
+            }
+        };
+
+        Duration::from_secs(base_secs) + jitter(config.jitter_secs)
+    }
+}
+
+fn jitter(max_secs: u64) -> Duration {
+    if max_secs == 0 {
+        return Duration::ZERO;
+    }
+
+    Duration::from_millis(rand::rng().random_range(0..=max_secs.saturating_mul(1000)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(jitter_secs: u64) -> PollConfig {
+        PollConfig {
+            interval_secs: 10,
+            jitter_secs,
+            max_backoff_secs: 60,
+            pending_work_interval_secs: 2,
+        }
+    }
+
+    fn delays_after_failures(failures: usize) -> Vec<u64> {
+        let config = config(0);
+        let mut schedule = PollSchedule::default();
+
+        (0..failures)
+            .map(|_| {
+                schedule.record(PollOutcome::EndpointUnreachable);
+                schedule.next_delay(&config, PollOutcome::EndpointUnreachable).as_secs()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn backs_off_exponentially_up_to_the_maximum() {
+        assert_eq!(delays_after_failures(5), vec![10, 20, 40, 60, 60]);
+    }
+
+    #[test]
+    fn backoff_does_not_overflow_after_many_failures() {
+        assert_eq!(delays_after_failures(100).last(), Some(&60));
+    }
+
+    #[test]
+    fn success_resets_the_backoff() {
+        let config = config(0);
+        let mut schedule = PollSchedule::default();
+
+        for _ in 0..3 {
+            schedule.record(PollOutcome::EndpointUnreachable);
+        }
+        schedule.record(PollOutcome::Succeeded);
+
+        assert_eq!(schedule.next_delay(&config, PollOutcome::Succeeded), Duration::from_secs(10));
+
+        schedule.record(PollOutcome::EndpointUnreachable);
+        assert_eq!(schedule.next_delay(&config, PollOutcome::EndpointUnreachable), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn pending_work_polls_again_soon_without_jitter() {
+        let schedule = PollSchedule::default();
+
+        assert_eq!(schedule.next_delay(&config(5), PollOutcome::PendingWork), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        let schedule = PollSchedule::default();
+
+        for _ in 0..100 {
+            let delay = schedule.next_delay(&config(5), PollOutcome::Succeeded);
+            assert!(delay >= Duration::from_secs(10) && delay <= Duration::from_secs(15), "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn a_maximum_below_the_interval_does_not_shorten_it() {
+        let config = PollConfig { max_backoff_secs: 1, ..config(0) };
+        let mut schedule = PollSchedule::default();
+        schedule.record(PollOutcome::EndpointUnreachable);
+
+        assert_eq!(schedule.next_delay(&config, PollOutcome::EndpointUnreachable), Duration::from_secs(10));
+    }
+}
@@ -10,15 +10,13 @@ use windows::core::PCWSTR;
 use windows::Win32::Storage::FileSystem::{SetFileAttributesW, FILE_ATTRIBUTE_HIDDEN};
 
 use crate::config::AgentConfig;
+use crate::storage;
 
 pub fn create_default_config_file_if_missing(path: &str) -> anyhow::Result<()> {
     let file_path = Path::new(path);
 
     if !file_path.exists() {
-        let config = serde_json::to_string_pretty(&AgentConfig::default())?; // Serialize to pretty JSON
-
-        fs::create_dir_all(file_path.parent().unwrap())?; // Ensure parent directories exist
-        fs::write(file_path, config)?;
+        storage::write_json_atomic(file_path, &AgentConfig::default())?;
         tracing::info!("Created default AgentConfig at {}", path);
     }
 
@@ -26,7 +24,7 @@ pub fn create_default_config_file_if_missing(path: &str) -> anyhow::Result<()> {
 }
 
 pub fn create_application_data_dir(root: &str) -> anyhow::Result<String> {
-    let agent_dir = format!("{}", root);
+    let agent_dir = root.to_string();
     fs::create_dir_all(&agent_dir)?; // Create the directory if it doesn't exist
 
     let extensions_dir = format!("{}\\extensions", agent_dir);
@@ -0,0 +1,456 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use cloudapi_sdk::model::extension::{ExtensionState, ExtensionStatus};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+use crate::constants;
+use crate::extension::read_extension_spec;
+use crate::service::schedule::PollOutcome;
+use crate::state::{ObservedExtension, StateDb};
+use crate::storage;
+
+const RUNNING_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum UpdatePhase {
+    /// The new binary is in place and has not yet reported in to the server.
+    PendingHealthCheck,
+    Committed,
+    RolledBack,
+}
+
+/**
+ * Progress of the last agent self-update, persisted so it survives the restart that
+ * hands over to the new binary.
+ */
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct AgentUpdate {
+    from_version: String,
+    to_version: String,
+    previous_binary: PathBuf,
+    started_at: DateTime<Utc>,
+    phase: UpdatePhase,
+    boot_attempts: u32,
+    last_error: Option<String>,
+}
+
+impl AgentUpdate {
+    fn path() -> PathBuf {
+        Path::new(constants::DEFAULT_CLOUD_API_ROOT_DIR).join(constants::AGENT_UPDATE_STATE_FILE)
+    }
+
+    fn load() -> Result<Option<Self>> {
+        storage::read_json(&Self::path())
+    }
+
+    fn save(&self) -> Result<()> {
+        storage::write_json_atomic(&Self::path(), self)
+    }
+}
+
+pub fn is_agent_extension(extension: &ExtensionState) -> bool {
+    extension.id == constants::AGENT_EXTENSION_ID
+}
+
+/**
+ * Reconciles the `cloudapi-agent` extension. Instead of running an install script, a
+ * differing version is downloaded, verified against the checksum in its assignment, staged in
+ * place of the running binary and handed over to by restarting the service. The new
+ * binary must report in before the health check expires or the previous one is restored.
+ */
+pub async fn reconcile_agent_update(extension: &ExtensionState, package_endpoint: &str, cache_dir: &str, state_db: &mut StateDb) -> Result<()> {
+    let update = AgentUpdate::load()?;
+
+    if let Some(update) = update.as_ref().filter(|update| update.to_version == extension.version) {
+        match update.phase {
+            UpdatePhase::PendingHealthCheck => {
+                tracing::info!("Agent update to {} is waiting for its health check.", update.to_version);
+                return Ok(());
+            }
+            UpdatePhase::RolledBack => {
+                let mut observed = ObservedExtension::new(extension, ExtensionStatus::Failed);
+                observed.version = RUNNING_VERSION.to_string();
+                observed.last_error = update.last_error.clone().or_else(|| Some(format!("Agent {} was rolled back", update.to_version)));
+                state_db.upsert(observed);
+                return Ok(());
+            }
+            UpdatePhase::Committed => {}
+        }
+    }
+
+    if extension.version == RUNNING_VERSION {
+        if state_db.get(&extension.uid).is_none_or(|observed| observed.status != ExtensionStatus::Installed) {
+            state_db.upsert(ObservedExtension::new(extension, ExtensionStatus::Installed));
+        }
+
+        return Ok(());
+    }
+
+    if matches!(extension.status, ExtensionStatus::Disabled | ExtensionStatus::Uninstalling | ExtensionStatus::Uninstalled) {
+        tracing::info!("Agent extension is {:?}, not updating.", extension.status);
+        return Ok(());
+    }
+
+    tracing::info!("Updating agent from {} to {}", RUNNING_VERSION, extension.version);
+
+    let mut observed = ObservedExtension::new(extension, ExtensionStatus::Installing);
+    observed.version = RUNNING_VERSION.to_string();
+    state_db.upsert(observed);
+
+    let new_binary = download_and_verify(extension, package_endpoint, cache_dir).await?;
+    let previous_binary = stage_binary(&new_binary)?;
+
+    let mut update = AgentUpdate {
+        from_version: RUNNING_VERSION.to_string(),
+        to_version: extension.version.clone(),
+        previous_binary,
+        started_at: Utc::now(),
+        phase: UpdatePhase::PendingHealthCheck,
+        boot_attempts: 0,
+        last_error: None,
+    };
+
+    update.save()?;
+
+    if let Err(e) = restart_service().await {
+        // Without the restart this process keeps running, so nothing would ever report in.
+        restore_previous_binary(&update, &std::env::current_exe()?)?;
+        update.phase = UpdatePhase::RolledBack;
+        update.last_error = Some(format!("{:#}", e));
+        update.save()?;
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+/**
+ * Why `reconcile_agent_update` would replace the running agent, or `None` if it would not.
+ */
+pub fn update_reason(extension: &ExtensionState) -> Result<Option<String>> {
+    Ok(reason_to_update(extension, AgentUpdate::load()?.as_ref()))
+}
+
+fn reason_to_update(extension: &ExtensionState, update: Option<&AgentUpdate>) -> Option<String> {
+    if extension.version == RUNNING_VERSION
+        || matches!(extension.status, ExtensionStatus::Disabled | ExtensionStatus::Uninstalling | ExtensionStatus::Uninstalled) {
+        return None;
+    }
+
+    match update.filter(|update| update.to_version == extension.version).map(|update| update.phase) {
+        Some(UpdatePhase::PendingHealthCheck) | Some(UpdatePhase::RolledBack) => None,
+        _ => Some(format!("running agent {}, assigned {}", RUNNING_VERSION, extension.version)),
+    }
+}
+
+/**
+ * Called as the agent starts, once it holds the instance lock. A new binary that keeps
+ * restarting without reporting in is rolled back once it exceeds the allowed boot
+ * attempts. If the previous binary was started instead, the staged one is taken out again.
+ */
+pub async fn on_startup() -> Result<()> {
+    let Some(mut update) = AgentUpdate::load()? else {
+        return Ok(());
+    };
+
+    if update.phase != UpdatePhase::PendingHealthCheck {
+        return Ok(());
+    }
+
+    if update.to_version != RUNNING_VERSION {
+        tracing::warn!("Agent update to {} did not take over, still running {}.", update.to_version, RUNNING_VERSION);
+
+        if !update.previous_binary.exists() {
+            tracing::warn!("Previous agent binary {} is gone, leaving the update record as it is.", update.previous_binary.to_string_lossy());
+            return Ok(());
+        }
+
+        restore_previous_binary(&update, &std::env::current_exe()?)?;
+        update.phase = UpdatePhase::RolledBack;
+        update.last_error = Some(format!("Agent {} did not start", update.to_version));
+        return update.save();
+    }
+
+    update.boot_attempts += 1;
+    update.save()?;
+
+    tracing::info!("Started updated agent {} (attempt {}), waiting to report in.", update.to_version, update.boot_attempts);
+
+    if update.boot_attempts > constants::AGENT_UPDATE_MAX_BOOT_ATTEMPTS {
+        return rollback(update, format!("Agent {} restarted {} times without reporting in", RUNNING_VERSION, constants::AGENT_UPDATE_MAX_BOOT_ATTEMPTS)).await;
+    }
+
+    Ok(())
+}
+
+/**
+ * Completes or rolls back a pending update based on the latest poll. Reaching the server
+ * counts as reporting in; the previous binary is then discarded.
+ */
+pub async fn check_health(outcome: PollOutcome) -> Result<()> {
+    let Some(mut update) = AgentUpdate::load()? else {
+        return Ok(());
+    };
+
+    if update.phase != UpdatePhase::PendingHealthCheck {
+        return Ok(());
+    }
+
+    if update.to_version != RUNNING_VERSION {
+        if expire_stalled_update(&mut update, &std::env::current_exe()?, Utc::now())? {
+            update.save()?;
+        }
+
+        return Ok(());
+    }
+
+    if outcome != PollOutcome::EndpointUnreachable {
+        tracing::info!("Agent {} reported in, committing update.", RUNNING_VERSION);
+
+        if let Err(e) = fs::remove_file(&update.previous_binary) {
+            tracing::warn!("Failed to remove previous agent binary {}: {:?}", update.previous_binary.to_string_lossy(), e);
+        }
+
+        update.phase = UpdatePhase::Committed;
+        return update.save();
+    }
+
+    if Utc::now() > health_check_deadline(&update) {
+        return rollback(update, format!("Agent {} did not report in within {}s", RUNNING_VERSION, constants::AGENT_UPDATE_HEALTH_CHECK_SECS)).await;
+    }
+
+    Ok(())
+}
+
+fn health_check_deadline(update: &AgentUpdate) -> DateTime<Utc> {
+    update.started_at + chrono::Duration::seconds(constants::AGENT_UPDATE_HEALTH_CHECK_SECS as i64)
+}
+
+/**
+ * Rolls back an update that is still pending while the previous agent keeps running, as
+ * when the restart that should have handed over never happened. Once the health check
+ * deadline has passed, the previous binary is put back in place of the staged one at
+ * `current` and the update is marked rolled back. Returns whether it was.
+ */
+fn expire_stalled_update(update: &mut AgentUpdate, current: &Path, now: DateTime<Utc>) -> Result<bool> {
+    if now <= health_check_deadline(update) {
+        return Ok(false);
+    }
+
+    let reason = format!("Agent {} did not take over within {}s", update.to_version, constants::AGENT_UPDATE_HEALTH_CHECK_SECS);
+    tracing::error!("Rolling back agent update: {}", reason);
+
+    restore_previous_binary(update, current)?;
+    update.phase = UpdatePhase::RolledBack;
+    update.last_error = Some(reason);
+
+    Ok(true)
+}
+
+async fn download_and_verify(extension: &ExtensionState, package_endpoint: &str, cache_dir: &str) -> Result<PathBuf> {
+    let expected = extension.package_sha256.as_deref()
+        .context(format!("Agent assignment {} carries no package checksum", extension.version))?
+        .to_lowercase();
+
+    let extension_pkg = format!("{}-{}.extpkg", extension.get_package_id(), extension.version);
+    let package_path = super::download_package(package_endpoint, &extension_pkg, cache_dir).await?;
+
+    let actual = format!("{:x}", Sha256::digest(fs::read(&package_path)?));
+
+    if actual != expected {
+        // Drop the cached package so the next attempt downloads it again.
+        let _ = fs::remove_file(&package_path);
+        return Err(anyhow::anyhow!("Checksum mismatch for {}: expected {}, got {}", extension_pkg, expected, actual));
+    }
+
+    let target_dir = Path::new(constants::DEFAULT_CLOUD_API_ROOT_DIR).join("agent").join(format!("v{}", extension.version));
+    super::extract_package(&package_path, &target_dir.to_string_lossy()).await?;
+
+    let spec = read_extension_spec(&target_dir)?
+        .context("Agent package does not contain an extension.spec")?;
+
+    if spec.id != constants::AGENT_EXTENSION_ID || spec.version != extension.version {
+        return Err(anyhow::anyhow!("Agent package is {} {}, expected {} {}", spec.id, spec.version, constants::AGENT_EXTENSION_ID, extension.version));
+    }
+
+    let binary = target_dir.join(format!("{}{}", constants::AGENT_EXTENSION_ID, std::env::consts::EXE_SUFFIX));
+
+    if !binary.is_file() {
+        return Err(anyhow::anyhow!("Agent package does not contain {}", binary.to_string_lossy()));
+    }
+
+    Ok(binary)
+}
+
+/**
+ * Moves the running binary aside and puts the new one in its place. Renaming works for
+ * a running executable on both Windows and Unix, unlike overwriting it.
+ */
+fn stage_binary(new_binary: &Path) -> Result<PathBuf> {
+    let current = std::env::current_exe()?;
+    let staged = current.with_extension("staged");
+    let previous = current.with_extension("previous");
+
+    fs::copy(new_binary, &staged)
+        .context(format!("Failed to stage agent binary: {}", staged.to_string_lossy()))?;
+    fs::rename(&current, &previous)
+        .context(format!("Failed to move aside agent binary: {}", current.to_string_lossy()))?;
+
+    if let Err(e) = fs::rename(&staged, &current) {
+        fs::rename(&previous, &current)?;
+        return Err(e).context("Failed to install staged agent binary");
+    }
+
+    tracing::info!("Staged new agent binary at {}", current.to_string_lossy());
+
+    Ok(previous)
+}
+
+async fn rollback(mut update: AgentUpdate, reason: String) -> Result<()> {
+    tracing::error!("Rolling back agent update: {}", reason);
+
+    restore_previous_binary(&update, &std::env::current_exe()?)?;
+
+    update.phase = UpdatePhase::RolledBack;
+    update.last_error = Some(reason);
+    update.save()?;
+
+    restart_service().await
+}
+
+/**
+ * Moves the binary at `current` aside and puts the one the update replaced back in its place.
+ */
+fn restore_previous_binary(update: &AgentUpdate, current: &Path) -> Result<()> {
+    let failed = current.with_extension("failed");
+
+    fs::rename(current, &failed)
+        .context(format!("Failed to move aside agent binary: {}", current.to_string_lossy()))?;
+    fs::rename(&update.previous_binary, current)
+        .context(format!("Failed to restore agent binary: {}", update.previous_binary.to_string_lossy()))?;
+
+    tracing::info!("Restored agent binary {} from {}", current.to_string_lossy(), update.previous_binary.to_string_lossy());
+
+    Ok(())
+}
+
+/**
+ * Asks the service manager to restart the agent so the binary now in place is started.
+ * The request is not waited on since it stops this process.
+ */
+async fn restart_service() -> Result<()> {
+    tracing::info!("Restarting service {} to hand over to the new binary.", constants::AGENT_SERVICE_NAME);
+
+    #[cfg(unix)]
+    let mut command = {
+        let mut command = Command::new("systemctl");
+        command.arg("restart").arg("--no-block").arg(format!("{}.service", constants::AGENT_SERVICE_NAME));
+        command
+    };
+
+    #[cfg(windows)]
+    let mut command = {
+        let mut command = Command::new("pwsh");
+        command.arg("-NoProfile").arg("-Command").arg(format!("Restart-Service -Name {} -Force", constants::AGENT_SERVICE_NAME));
+        command
+    };
+
+    command.spawn().context("Failed to request service restart")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agent_extension(version: &str) -> ExtensionState {
+        let mut extension = ExtensionState::new("uid-agent", constants::AGENT_EXTENSION_ID, version);
+        extension.set_publisher("test");
+        extension
+    }
+
+    fn update(to_version: &str, phase: UpdatePhase) -> AgentUpdate {
+        AgentUpdate {
+            from_version: RUNNING_VERSION.to_string(),
+            to_version: to_version.to_string(),
+            previous_binary: PathBuf::from("cloudapi-agent.previous"),
+            started_at: Utc::now(),
+            phase,
+            boot_attempts: 0,
+            last_error: None,
+        }
+    }
+
+    #[test]
+    fn updates_to_a_different_assigned_version() {
+        assert!(is_agent_extension(&agent_extension("99.0.0")));
+        assert!(reason_to_update(&agent_extension("99.0.0"), None).is_some());
+        assert!(reason_to_update(&agent_extension(RUNNING_VERSION), None).is_none());
+    }
+
+    #[test]
+    fn does_not_update_a_disabled_agent_extension() {
+        let mut extension = agent_extension("99.0.0");
+        extension.set_status(ExtensionStatus::Disabled);
+
+        assert!(reason_to_update(&extension, None).is_none());
+    }
+
+    #[test]
+    fn does_not_retry_a_pending_or_rolled_back_update() {
+        let extension = agent_extension("99.0.0");
+
+        assert!(reason_to_update(&extension, Some(&update("99.0.0", UpdatePhase::PendingHealthCheck))).is_none());
+        assert!(reason_to_update(&extension, Some(&update("99.0.0", UpdatePhase::RolledBack))).is_none());
+        assert!(reason_to_update(&extension, Some(&update("98.0.0", UpdatePhase::RolledBack))).is_some());
+    }
+
+    fn staged_update(dir: &Path, started_at: DateTime<Utc>) -> (AgentUpdate, PathBuf) {
+        let current = dir.join("cloudapi-agent");
+        let mut update = update("99.0.0", UpdatePhase::PendingHealthCheck);
+        update.previous_binary = current.with_extension("previous");
+        update.started_at = started_at;
+
+        fs::write(&current, "staged").unwrap();
+        fs::write(&update.previous_binary, "previous").unwrap();
+
+        (update, current)
+    }
+
+    #[test]
+    fn a_stalled_update_is_left_pending_until_the_deadline() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut update, current) = staged_update(dir.path(), Utc::now());
+
+        assert!(!expire_stalled_update(&mut update, &current, Utc::now()).unwrap());
+        assert_eq!(update.phase, UpdatePhase::PendingHealthCheck);
+        assert_eq!(fs::read_to_string(&current).unwrap(), "staged");
+    }
+
+    #[test]
+    fn a_stalled_update_restores_the_previous_binary_after_the_deadline() {
+        let dir = tempfile::tempdir().unwrap();
+        let started_at = Utc::now() - chrono::Duration::seconds(constants::AGENT_UPDATE_HEALTH_CHECK_SECS as i64 + 1);
+        let (mut update, current) = staged_update(dir.path(), started_at);
+
+        assert!(expire_stalled_update(&mut update, &current, Utc::now()).unwrap());
+        assert_eq!(update.phase, UpdatePhase::RolledBack);
+        assert!(update.last_error.as_deref().unwrap().contains("did not take over"));
+        assert_eq!(fs::read_to_string(&current).unwrap(), "previous");
+        assert_eq!(fs::read_to_string(current.with_extension("failed")).unwrap(), "staged");
+        assert!(!update.previous_binary.exists());
+    }
+
+    #[tokio::test]
+    async fn refuses_an_assignment_without_a_checksum() {
+        let error = download_and_verify(&agent_extension("99.0.0"), "http://127.0.0.1:1", "unused").await.unwrap_err();
+
+        assert!(error.to_string().contains("carries no package checksum"), "{:#}", error);
+    }
+}
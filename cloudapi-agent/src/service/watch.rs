@@ -0,0 +1,133 @@
+use anyhow::{Context, Result};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Notify};
+
+use crate::constants;
+
+/**
+ * Watches the agent config and wakes `config_changed` when its contents change. The
+ * parent directory is watched rather than the file so editors that save by replacing the
+ * file are still seen. Events for other files, such as the state the agent writes itself,
+ * are ignored, as are writes that leave the contents unchanged.
+ *
+ * The watch stops when the returned watcher is dropped.
+ */
+pub fn watch_config(config_file: &Path, config_changed: Arc<Notify>) -> Result<RecommendedWatcher> {
+    let config_file = config_file.to_path_buf();
+    let watch_dir = config_file.parent()
+        .context(format!("Config file has no parent directory: {}", config_file.to_string_lossy()))?
+        .to_path_buf();
+    let file_name = config_file.file_name()
+        .context(format!("Config path has no file name: {}", config_file.to_string_lossy()))?
+        .to_os_string();
+
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        match event {
+            Ok(event) if event.paths.iter().any(|path| path.file_name() == Some(file_name.as_os_str())) => {
+                let _ = sender.send(());
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Config watch error: {:?}", e),
+        }
+    })?;
+
+    watcher.watch(&watch_dir, RecursiveMode::NonRecursive)
+        .context(format!("Failed to watch {}", watch_dir.to_string_lossy()))?;
+
+    tracing::info!("Watching {} for changes.", config_file.to_string_lossy());
+    // Hashed before returning, so a change made right after the watch starts is not missed.
+    let last_hash = hash_file(&config_file);
+    tokio::spawn(debounce_changes(config_file, last_hash, receiver, config_changed));
+
+    Ok(watcher)
+}
+
+async fn debounce_changes(config_file: PathBuf, mut last_hash: Option<String>, mut receiver: mpsc::UnboundedReceiver<()>, config_changed: Arc<Notify>) {
+    let debounce = Duration::from_millis(constants::CONFIG_WATCH_DEBOUNCE_MS);
+
+    while receiver.recv().await.is_some() {
+        // Let a burst of events from a single save settle before looking at the file.
+        while let Ok(Some(())) = tokio::time::timeout(debounce, receiver.recv()).await {}
+
+        let hash = hash_file(&config_file);
+
+        if hash == last_hash {
+            continue;
+        }
+
+        last_hash = hash;
+        tracing::info!("Config file {} changed.", config_file.to_string_lossy());
+        config_changed.notify_one();
+    }
+}
+
+fn hash_file(path: &Path) -> Option<String> {
+    std::fs::read(path).ok().map(|contents| format!("{:x}", Sha256::digest(contents)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn start(config_file: &Path) -> (mpsc::UnboundedSender<()>, Arc<Notify>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let config_changed = Arc::new(Notify::new());
+        tokio::spawn(debounce_changes(config_file.to_path_buf(), hash_file(config_file), receiver, config_changed.clone()));
+
+        (sender, config_changed)
+    }
+
+    /// Time is paused in these tests, so waiting only advances the clock once every task is idle.
+    async fn notified(config_changed: &Notify) -> bool {
+        let wait = Duration::from_millis(constants::CONFIG_WATCH_DEBOUNCE_MS * 3);
+        tokio::time::timeout(wait, config_changed.notified()).await.is_ok()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rewriting_the_same_contents_is_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_file = dir.path().join("config.json");
+        fs::write(&config_file, "{}").unwrap();
+        let (sender, config_changed) = start(&config_file);
+
+        fs::write(&config_file, "{}").unwrap();
+        sender.send(()).unwrap();
+
+        assert!(!notified(&config_changed).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_burst_of_events_for_a_change_notifies_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_file = dir.path().join("config.json");
+        fs::write(&config_file, "{}").unwrap();
+        let (sender, config_changed) = start(&config_file);
+
+        fs::write(&config_file, "{\"log_level\": \"debug\"}").unwrap();
+        for _ in 0..3 {
+            sender.send(()).unwrap();
+        }
+
+        assert!(notified(&config_changed).await);
+        assert!(!notified(&config_changed).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_created_config_file_is_a_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_file = dir.path().join("config.json");
+        let (sender, config_changed) = start(&config_file);
+
+        fs::write(&config_file, "{}").unwrap();
+        sender.send(()).unwrap();
+
+        assert!(notified(&config_changed).await);
+    }
+}
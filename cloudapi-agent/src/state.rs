@@ -0,0 +1,267 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use cloudapi_sdk::model::extension::{ExtensionState, ExtensionStatus};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use crate::constants;
+use crate::storage;
+
+/**
+ * The extension assignments last received from the server. This is a cache of the
+ * desired state so the agent can keep reconciling while the endpoint is unreachable.
+ */
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct DesiredState {
+    pub fetched_at: Option<String>,
+    pub extensions: Vec<ExtensionState>,
+}
+
+impl DesiredState {
+    pub fn new(extensions: Vec<ExtensionState>) -> Self {
+        DesiredState {
+            fetched_at: Some(Utc::now().to_rfc3339()),
+            extensions,
+        }
+    }
+
+    pub fn default_path() -> PathBuf {
+        Path::new(constants::DEFAULT_CLOUD_API_ROOT_DIR).join(constants::DESIRED_STATE_FILE)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(storage::read_json(path)?.unwrap_or_default())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        storage::write_json_atomic(path, self)
+    }
+
+    pub fn get_extensions(&self) -> &Vec<ExtensionState> {
+        &self.extensions
+    }
+}
+
+/**
+ * What the agent has observed about an extension on this machine.
+ */
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ObservedExtension {
+    pub uid: String,
+    pub package_id: String,
+    pub version: String,
+    pub status: ExtensionStatus,
+    pub version_hash: Option<String>,
+    pub last_error: Option<String>,
+    pub updated_at: String,
+}
+
+impl ObservedExtension {
+    pub fn new(state: &ExtensionState, status: ExtensionStatus) -> Self {
+        ObservedExtension {
+            uid: state.uid.clone(),
+            package_id: state.get_package_id(),
+            version: state.version.clone(),
+            status,
+            version_hash: None,
+            last_error: None,
+            updated_at: Utc::now().to_rfc3339(),
+        }
+    }
+}
+
+/**
+ * Agent-local state database, keyed by extension uid. Only the agent writes this file.
+ */
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct StateDb {
+    pub extensions: BTreeMap<String, ObservedExtension>,
+    /// The metadata endpoint that last answered, preferred on the next start.
+    #[serde(default)]
+    pub last_healthy_endpoint: Option<String>,
+    /// Scheduled events already delivered to every subscribed extension and acknowledged.
+    #[serde(default)]
+    pub forwarded_events: BTreeSet<String>,
+    /// The uids of the extensions that handled each scheduled event still in progress, so
+    /// a retry only runs the handlers that have not succeeded yet.
+    #[serde(default)]
+    pub event_deliveries: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl StateDb {
+    pub fn default_path() -> PathBuf {
+        Path::new(constants::DEFAULT_CLOUD_API_ROOT_DIR).join(constants::STATE_DB_FILE)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(storage::read_json(path)?.unwrap_or_default())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        storage::write_json_atomic(path, self)
+    }
+
+    pub fn get(&self, uid: &str) -> Option<&ObservedExtension> {
+        self.extensions.get(uid)
+    }
+
+    pub fn upsert(&mut self, mut observed: ObservedExtension) {
+        observed.updated_at = Utc::now().to_rfc3339();
+        self.extensions.insert(observed.uid.clone(), observed);
+    }
+
+    pub fn remove(&mut self, uid: &str) -> Option<ObservedExtension> {
+        self.extensions.remove(uid)
+    }
+}
+
+/**
+ * Moves the `extensions` list that agent.config.json held before desired and observed
+ * state were split out of it. The list seeds the desired state, without a fetch time so
+ * nothing is pruned against it, and every extension with a `VERSION` marker on disk is
+ * tracked as installed. The list is removed from the config last, so an interrupted
+ * migration runs again on the next start.
+ */
+pub fn migrate_legacy_config(config_path: &Path, desired_state_path: &Path, state_db_path: &Path) -> Result<()> {
+    let Some(mut config) = storage::read_json::<serde_json::Value>(config_path)? else {
+        return Ok(());
+    };
+
+    let Some(legacy) = config.as_object_mut().and_then(|config| config.remove("extensions")) else {
+        return Ok(());
+    };
+
+    let extensions: Vec<ExtensionState> = serde_json::from_value(legacy)
+        .context(format!("Failed to parse legacy extensions in {}", config_path.to_string_lossy()))?;
+
+    tracing::info!("Migrating {} extension(s) out of {}", extensions.len(), config_path.to_string_lossy());
+
+    if !desired_state_path.exists() {
+        DesiredState { fetched_at: None, extensions: extensions.clone() }.save(desired_state_path)?;
+    }
+
+    let mut state_db = StateDb::load(state_db_path)?;
+
+    for extension in &extensions {
+        let version_file = PathBuf::from(format!("{}\\extensions\\{}", constants::DEFAULT_CLOUD_API_ROOT_DIR, extension.get_package_id())).join("VERSION");
+
+        if state_db.get(&extension.uid).is_some() || !version_file.exists() {
+            continue;
+        }
+
+        let mut observed = ObservedExtension::new(extension, ExtensionStatus::Installed);
+        observed.version_hash = Some(std::fs::read_to_string(&version_file)?.trim().to_string());
+        state_db.upsert(observed);
+    }
+
+    state_db.save(state_db_path)?;
+    storage::write_json_atomic(config_path, &config)
+}
+
+/**
+ * Snapshot of the running agent, rewritten after every poll so local tooling can see what
+ * the daemon is doing without talking to it.
+ */
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RuntimeStatus {
+    pub pid: u32,
+    pub agent_version: String,
+    pub started_at: String,
+    pub updated_at: String,
+    pub active_endpoint: Option<String>,
+    pub last_outcome: Option<String>,
+    pub next_poll_at: Option<String>,
+}
+
+impl RuntimeStatus {
+    pub fn default_path() -> PathBuf {
+        Path::new(constants::DEFAULT_CLOUD_API_ROOT_DIR).join(constants::RUNTIME_STATUS_FILE)
+    }
+
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        storage::read_json(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        storage::write_json_atomic(path, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    struct Paths {
+        _dir: tempfile::TempDir,
+        config: PathBuf,
+        desired_state: PathBuf,
+        state_db: PathBuf,
+    }
+
+    fn paths() -> Paths {
+        let dir = tempfile::tempdir().unwrap();
+
+        Paths {
+            config: dir.path().join("agent.config.json"),
+            desired_state: dir.path().join("desired-state.json"),
+            state_db: dir.path().join("state.json"),
+            _dir: dir,
+        }
+    }
+
+    fn legacy_extension(uid: &str) -> Value {
+        json!({
+            "uid": uid,
+            "id": "migration-test-extension",
+            "publisher": "test",
+            "version": "1.0.0",
+            "config": null,
+            "status": "installed",
+            "modified_at": "2025-11-01T12:00:00Z"
+        })
+    }
+
+    #[test]
+    fn moves_legacy_extensions_into_the_desired_state() {
+        let paths = paths();
+        storage::write_json_atomic(&paths.config, &json!({
+            "cloudapi_endpoints": ["http://example"],
+            "extensions": [legacy_extension("uid-1")]
+        })).unwrap();
+
+        migrate_legacy_config(&paths.config, &paths.desired_state, &paths.state_db).unwrap();
+
+        let desired_state = DesiredState::load(&paths.desired_state).unwrap();
+        assert!(desired_state.fetched_at.is_none(), "a migrated list must not be pruned against");
+        assert_eq!(desired_state.extensions.len(), 1);
+        assert_eq!(desired_state.extensions[0].uid, "uid-1");
+
+        let config: Value = storage::read_json(&paths.config).unwrap().unwrap();
+        assert_eq!(config, json!({ "cloudapi_endpoints": ["http://example"] }));
+    }
+
+    #[test]
+    fn keeps_an_existing_desired_state() {
+        let paths = paths();
+        DesiredState::new(Vec::new()).save(&paths.desired_state).unwrap();
+        storage::write_json_atomic(&paths.config, &json!({ "extensions": [legacy_extension("uid-1")] })).unwrap();
+
+        migrate_legacy_config(&paths.config, &paths.desired_state, &paths.state_db).unwrap();
+
+        assert!(DesiredState::load(&paths.desired_state).unwrap().extensions.is_empty());
+        assert_eq!(storage::read_json::<Value>(&paths.config).unwrap(), Some(json!({})));
+    }
+
+    #[test]
+    fn leaves_a_migrated_config_alone() {
+        let paths = paths();
+        storage::write_json_atomic(&paths.config, &json!({ "cloudapi_endpoints": [] })).unwrap();
+
+        migrate_legacy_config(&paths.config, &paths.desired_state, &paths.state_db).unwrap();
+
+        assert!(!paths.desired_state.exists());
+        assert!(!paths.state_db.exists());
+    }
+}
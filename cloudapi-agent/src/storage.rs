@@ -0,0 +1,97 @@
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/**
+ * Writes `contents` to `path` by writing a sibling temp file, flushing it to disk and
+ * renaming it over the destination. Readers either see the old file or the new one,
+ * never a partially written file.
+ */
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let parent = path.parent()
+        .context(format!("Path has no parent directory: {}", path.to_string_lossy()))?;
+    fs::create_dir_all(parent)?;
+
+    let file_name = path.file_name()
+        .context(format!("Path has no file name: {}", path.to_string_lossy()))?
+        .to_string_lossy();
+    let tmp_path = parent.join(format!(".{}.{}.tmp", file_name, std::process::id()));
+
+    {
+        let mut file = fs::File::create(&tmp_path)
+            .context(format!("Failed to create temp file: {}", tmp_path.to_string_lossy()))?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+
+    if let Err(e) = fs::rename(&tmp_path, path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e).context(format!("Failed to replace file: {}", path.to_string_lossy()));
+    }
+
+    Ok(())
+}
+
+pub fn write_json_atomic<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let contents = serde_json::to_vec_pretty(value)?;
+    write_atomic(path, &contents)
+}
+
+/**
+ * Reads a JSON document, returning `None` when the file does not exist yet.
+ */
+pub fn read_json<T: DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let contents = fs::read_to_string(path)
+        .context(format!("Failed to read file: {}", path.to_string_lossy()))?;
+    let value = serde_json::from_str(&contents)
+        .context(format!("Failed to parse file: {}", path.to_string_lossy()))?;
+
+    Ok(Some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn write_atomic_creates_and_replaces_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("state.json");
+
+        write_atomic(&path, b"first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+
+        let entries: Vec<_> = fs::read_dir(path.parent().unwrap()).unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, vec!["state.json"], "temp files must not be left behind");
+    }
+
+    #[test]
+    fn read_json_returns_none_for_a_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert!(read_json::<Value>(&dir.path().join("missing.json")).unwrap().is_none());
+    }
+
+    #[test]
+    fn read_json_round_trips_and_rejects_invalid_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+
+        write_json_atomic(&path, &json!({ "a": 1 })).unwrap();
+        assert_eq!(read_json::<Value>(&path).unwrap(), Some(json!({ "a": 1 })));
+
+        fs::write(&path, "{ not json").unwrap();
+        assert!(read_json::<Value>(&path).is_err());
+    }
+}
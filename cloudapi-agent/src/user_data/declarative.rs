@@ -0,0 +1,239 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use tokio::process::Command;
+
+/**
+ * A small declarative bootstrap format, written as JSON after a `#cloud-api-config`
+ * header line. Files are written first, then users are created, then commands run.
+ */
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct CloudApiConfig {
+    pub write_files: Vec<WriteFile>,
+    pub users: Vec<User>,
+    pub run_commands: Vec<RunCommand>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WriteFile {
+    pub path: String,
+    pub content: String,
+    /// Octal mode, e.g. `0644`. Ignored on Windows.
+    pub permissions: Option<String>,
+    #[serde(default)]
+    pub append: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct User {
+    pub name: String,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    pub shell: Option<String>,
+    #[serde(default)]
+    pub ssh_authorized_keys: Vec<String>,
+}
+
+/**
+ * A command is either an argument vector executed directly or a string run by the
+ * platform shell.
+ */
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RunCommand {
+    Args(Vec<String>),
+    Shell(String),
+}
+
+/**
+ * Applies the config, returning the combined output of the commands that were run.
+ */
+pub async fn apply(config: &CloudApiConfig) -> Result<(i32, String, String)> {
+    for file in &config.write_files {
+        write_file(file).context(format!("Failed to write file: {}", file.path))?;
+    }
+
+    for user in &config.users {
+        create_user(user).await.context(format!("Failed to create user: {}", user.name))?;
+    }
+
+    let mut stdout = String::new();
+    let mut stderr = String::new();
+
+    for command in &config.run_commands {
+        let output = build_command(command)?
+            .kill_on_drop(true)
+            .output()
+            .await
+            .context(format!("Failed to run command: {:?}", command))?;
+
+        stdout.push_str(&String::from_utf8_lossy(&output.stdout));
+        stderr.push_str(&String::from_utf8_lossy(&output.stderr));
+
+        let exit_code = output.status.code().unwrap_or(-1);
+
+        if exit_code != 0 {
+            tracing::error!("User-data command {:?} failed with code {}", command, exit_code);
+            return Ok((exit_code, stdout, stderr));
+        }
+    }
+
+    Ok((0, stdout, stderr))
+}
+
+fn write_file(file: &WriteFile) -> Result<()> {
+    let path = Path::new(&file.path);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if file.append {
+        use std::io::Write;
+        fs::OpenOptions::new().create(true).append(true).open(path)?.write_all(file.content.as_bytes())?;
+    } else {
+        fs::write(path, &file.content)?;
+    }
+
+    #[cfg(unix)]
+    if let Some(permissions) = &file.permissions {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = u32::from_str_radix(permissions.trim_start_matches("0o"), 8)
+            .context(format!("Invalid permissions: {}", permissions))?;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+
+    tracing::info!("Wrote file: {}", file.path);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn create_user(user: &User) -> Result<()> {
+    let exists = Command::new("id").arg(&user.name).output().await?.status.success();
+
+    if !exists {
+        let mut command = Command::new("useradd");
+        command.arg("--create-home");
+
+        if !user.groups.is_empty() {
+            command.arg("--groups").arg(user.groups.join(","));
+        }
+
+        if let Some(shell) = &user.shell {
+            command.arg("--shell").arg(shell);
+        }
+
+        let status = command.arg(&user.name).status().await?;
+
+        if !status.success() {
+            return Err(anyhow::anyhow!("useradd exited with {}", status));
+        }
+
+        tracing::info!("Created user: {}", user.name);
+    }
+
+    if !user.ssh_authorized_keys.is_empty() {
+        let ssh_dir = Path::new("/home").join(&user.name).join(".ssh");
+        let keys_file = ssh_dir.join("authorized_keys");
+
+        fs::create_dir_all(&ssh_dir)?;
+        fs::write(&keys_file, format!("{}\n", user.ssh_authorized_keys.join("\n")))?;
+
+        let owner = format!("{0}:{0}", user.name);
+        Command::new("chown").arg("-R").arg(&owner).arg(&ssh_dir).status().await?;
+        Command::new("chmod").arg("700").arg(&ssh_dir).status().await?;
+        Command::new("chmod").arg("600").arg(&keys_file).status().await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(windows)]
+async fn create_user(user: &User) -> Result<()> {
+    let exists = Command::new("net").arg("user").arg(&user.name).output().await?.status.success();
+
+    if !exists {
+        let status = Command::new("net").arg("user").arg(&user.name).arg("/add").status().await?;
+
+        if !status.success() {
+            return Err(anyhow::anyhow!("net user exited with {}", status));
+        }
+
+        tracing::info!("Created user: {}", user.name);
+    }
+
+    for group in &user.groups {
+        Command::new("net").arg("localgroup").arg(group).arg(&user.name).arg("/add").status().await?;
+    }
+
+    Ok(())
+}
+
+fn build_command(command: &RunCommand) -> Result<Command> {
+    match command {
+        RunCommand::Args(args) => {
+            let (program, args) = args.split_first()
+                .context("Command must not be empty")?;
+            let mut command = Command::new(program);
+            command.args(args);
+            Ok(command)
+        }
+        RunCommand::Shell(script) => {
+            #[cfg(unix)]
+            let mut command = Command::new("sh");
+            #[cfg(unix)]
+            command.arg("-c").arg(script);
+
+            #[cfg(windows)]
+            let mut command = Command::new("pwsh");
+            #[cfg(windows)]
+            command.arg("-NoProfile").arg("-Command").arg(script);
+
+            Ok(command)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &Path, content: &str, append: bool) -> WriteFile {
+        WriteFile {
+            path: path.to_string_lossy().to_string(),
+            content: content.to_string(),
+            permissions: Some("0600".to_string()),
+            append,
+        }
+    }
+
+    #[test]
+    fn writes_and_appends_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("motd");
+
+        write_file(&file(&path, "first\n", false)).unwrap();
+        write_file(&file(&path, "second\n", true)).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first\nsecond\n");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_invalid_permissions() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut file = file(&dir.path().join("motd"), "", false);
+        file.permissions = Some("rw-r--r--".to_string());
+
+        assert!(write_file(&file).is_err());
+    }
+}
@@ -0,0 +1,213 @@
+pub mod declarative;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use cloudapi_sdk::client::CloudApiClient;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::process::Command;
+
+use crate::config::UserDataConfig;
+use crate::constants;
+use declarative::CloudApiConfig;
+
+pub const CONFIG_HEADER: &str = "#cloud-api-config";
+
+/**
+ * Output of a user-data script, saved as `run-log.json` in the instance directory.
+ */
+#[derive(Debug, Serialize)]
+struct ScriptRunLog {
+    executed_at: String,
+    exit_code: i32,
+    stdout: String,
+    stderr: String,
+}
+
+/**
+ * User-data is either a script (anything that is not a declarative config) or a
+ * `#cloud-api-config` header followed by a JSON `CloudApiConfig`.
+ */
+pub enum UserData {
+    Script(String),
+    Config(CloudApiConfig),
+}
+
+impl UserData {
+    pub fn parse(user_data: &str) -> Result<Self> {
+        match user_data.trim_start().strip_prefix(CONFIG_HEADER) {
+            Some(config) => Ok(UserData::Config(
+                serde_json::from_str(config).context("Failed to parse #cloud-api-config user-data")?
+            )),
+            None => Ok(UserData::Script(user_data.to_string())),
+        }
+    }
+}
+
+fn get_instance_dir(instance_id: &str) -> PathBuf {
+    let mut sanitized: String = instance_id.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '_' })
+        .collect();
+
+    // An empty id, `.` or `..` would resolve to the user-data dir itself or its parent.
+    if sanitized.chars().all(|c| c == '.') {
+        sanitized = "_".repeat(sanitized.len().max(1));
+    }
+
+    Path::new(constants::DEFAULT_CLOUD_API_ROOT_DIR).join("user-data").join(sanitized)
+}
+
+/**
+ * Fetches and executes this machine's user-data once per instance id. Completion is
+ * recorded with a `ran.lock` marker next to a `run-log.json`, so the user-data does
+ * not run again on restart, but does run on a clone that gets a new instance id. A
+ * script that fails still counts as run; fetch errors are returned so the caller can
+ * retry later.
+ */
+pub async fn run_once(client: &CloudApiClient, config: &UserDataConfig) -> Result<()> {
+    let metadata = client.get_metadata().await.context("Failed to get instance metadata")?;
+    let instance_dir = get_instance_dir(&metadata.instance_id);
+    let ran_marker = instance_dir.join("ran.lock");
+
+    if ran_marker.exists() {
+        tracing::info!("User-data already ran for instance {}.", metadata.instance_id);
+        return Ok(());
+    }
+
+    fs::create_dir_all(&instance_dir)?;
+
+    let user_data = client.get_user_data().await.context("Failed to get user-data")?;
+
+    let log = match user_data {
+        None => {
+            tracing::info!("No user-data assigned to instance {}.", metadata.instance_id);
+            None
+        }
+        Some(user_data) => {
+            tracing::info!("Running user-data for instance {}...", metadata.instance_id);
+            let timeout = Duration::from_secs(config.timeout_secs);
+            let result = tokio::time::timeout(timeout, execute(&user_data, &instance_dir)).await;
+
+            let (exit_code, stdout, stderr) = match result {
+                Ok(Ok(output)) => output,
+                Ok(Err(e)) => (-1, String::new(), format!("{:#}", e)),
+                Err(_) => (-1, String::new(), format!("User-data timed out after {:?}", timeout)),
+            };
+
+            if exit_code != 0 {
+                tracing::error!("User-data for instance {} failed with code {}: {}", metadata.instance_id, exit_code, stderr);
+            } else {
+                tracing::info!("User-data for instance {} completed.", metadata.instance_id);
+            }
+
+            Some(ScriptRunLog {
+                executed_at: Utc::now().to_rfc3339(),
+                exit_code,
+                stdout,
+                stderr,
+            })
+        }
+    };
+
+    if let Some(log) = &log {
+        fs::write(instance_dir.join("run-log.json"), serde_json::to_vec_pretty(log)?)?;
+    }
+
+    fs::write(&ran_marker, Utc::now().to_rfc3339().as_bytes())?;
+
+    Ok(())
+}
+
+async fn execute(user_data: &str, instance_dir: &Path) -> Result<(i32, String, String)> {
+    match UserData::parse(user_data)? {
+        UserData::Config(config) => declarative::apply(&config).await,
+        UserData::Script(script) => {
+            let output = script_command(&script, instance_dir)?
+                .kill_on_drop(true)
+                .output()
+                .await
+                .context("Failed to execute user-data script")?;
+
+            Ok((
+                output.status.code().unwrap_or(-1),
+                String::from_utf8_lossy(&output.stdout).to_string(),
+                String::from_utf8_lossy(&output.stderr).to_string(),
+            ))
+        }
+    }
+}
+
+#[cfg(unix)]
+fn script_command(script: &str, instance_dir: &Path) -> Result<Command> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let script_path = instance_dir.join("user-data.sh");
+    fs::write(&script_path, script)?;
+    fs::set_permissions(&script_path, fs::Permissions::from_mode(0o700))?;
+
+    // Scripts without a shebang are run by sh.
+    if script.starts_with("#!") {
+        Ok(Command::new(script_path))
+    } else {
+        let mut command = Command::new("sh");
+        command.arg(script_path);
+        Ok(command)
+    }
+}
+
+#[cfg(windows)]
+fn script_command(script: &str, instance_dir: &Path) -> Result<Command> {
+    let script_path = instance_dir.join("user-data.ps1");
+    fs::write(&script_path, script)?;
+
+    let mut command = Command::new("pwsh");
+    command.arg("-NoProfile").arg("-ExecutionPolicy").arg("Bypass").arg("-File").arg(script_path);
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use declarative::RunCommand;
+
+    #[test]
+    fn anything_without_the_header_is_a_script() {
+        let script = "#!/bin/sh\necho hello\n";
+
+        assert!(matches!(UserData::parse(script).unwrap(), UserData::Script(parsed) if parsed == script));
+        assert!(matches!(UserData::parse("{\"run_commands\": []}").unwrap(), UserData::Script(_)));
+    }
+
+    #[test]
+    fn parses_a_declarative_config_after_the_header() {
+        let user_data = "\n  #cloud-api-config\n{\"run_commands\": [[\"echo\", \"hi\"], \"echo there\"]}";
+
+        let UserData::Config(config) = UserData::parse(user_data).unwrap() else {
+            panic!("expected a declarative config");
+        };
+
+        assert!(config.write_files.is_empty() && config.users.is_empty());
+        assert!(matches!(&config.run_commands[0], RunCommand::Args(args) if args == &["echo", "hi"]));
+        assert!(matches!(&config.run_commands[1], RunCommand::Shell(command) if command == "echo there"));
+    }
+
+    #[test]
+    fn rejects_an_invalid_declarative_config() {
+        assert!(UserData::parse("#cloud-api-config\n{ not json").is_err());
+    }
+
+    #[test]
+    fn instance_dirs_cannot_escape_the_user_data_dir() {
+        let dir = get_instance_dir("../../etc/passwd");
+
+        assert_eq!(dir.file_name().unwrap(), ".._.._etc_passwd");
+        assert_eq!(dir.parent().unwrap(), Path::new(constants::DEFAULT_CLOUD_API_ROOT_DIR).join("user-data"));
+
+        for instance_id in ["", ".", ".."] {
+            let dir = get_instance_dir(instance_id);
+            assert_eq!(dir.parent().unwrap(), Path::new(constants::DEFAULT_CLOUD_API_ROOT_DIR).join("user-data"), "{:?}", instance_id);
+        }
+    }
+}
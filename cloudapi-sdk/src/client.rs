@@ -1,52 +1,653 @@
-use reqwest::Client;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::model::{compute::MetadataResponse, extension::ExtensionState};
+use chrono::{DateTime, Utc};
+use reqwest::{header::HeaderMap, Client, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use tokio::sync::Mutex;
+
+use crate::model::auth::{Credential, RegistrationRequest};
+use crate::model::events::{AcknowledgeEventsRequest, ScheduledEvents};
+use crate::model::heartbeat::AgentHeartbeat;
+use crate::model::status::VirtualMachineStatus;
+use crate::model::{compute::MetadataResponse, extension::{ExtensionList, ExtensionState}};
+use crate::retry::RetryPolicy;
 
 use super::error::CloudApiError;
 
+pub const PENDING_WORK_HEADER: &str = "X-Cloud-Api-Pending-Work";
+
+const METADATA_PATH: &str = "/cloud-api/v1/metadata";
+
+const REGISTER_PATH: &str = "/cloud-api/v1/register";
+
+/// Client for the cloud-api metadata service. It holds an ordered list of endpoints and
+/// fails over to the next one when the active endpoint stops answering. Clones share
+/// the active endpoint.
 #[derive(Debug, Clone)]
 pub struct CloudApiClient {
-    endpoint: String,
+    endpoints: Arc<Vec<String>>,
+    active: Arc<AtomicUsize>,
     client: Client,
+    retry_policy: RetryPolicy,
+    auth: Arc<Mutex<AuthState>>,
+    /// Held while a credential is being obtained, so concurrent callers wait for a single
+    /// registration or refresh instead of each starting their own.
+    refresh: Arc<Mutex<()>>,
 }
 
-impl CloudApiClient {
+/// How a request may be sent again when it fails.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Delivery {
+    /// Safe to repeat: retried per the retry policy and failed over when unreachable.
+    Idempotent,
+    /// Sent again only when no connection could be made, so the server never saw it.
+    AtMostOnce,
+}
+
+/// The registration used to obtain credentials and the credential currently in use.
+#[derive(Debug, Default)]
+struct AuthState {
+    registration: Option<RegistrationRequest>,
+    credential: Option<Credential>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CloudApiClientBuilder {
+    endpoints: Vec<String>,
+    preferred_endpoint: Option<String>,
+    connect_timeout: Duration,
+    request_timeout: Duration,
+    retry_policy: RetryPolicy,
+}
+
+impl CloudApiClientBuilder {
     pub fn new(endpoint: impl Into<String>) -> Self {
+        Self::with_endpoints(vec![endpoint.into()])
+    }
+
+    /// Creates a builder for an ordered list of endpoints, tried first to last.
+    pub fn with_endpoints(endpoints: Vec<String>) -> Self {
         Self {
-            endpoint: endpoint.into(),
-            client: Client::new(),
+            endpoints,
+            preferred_endpoint: None,
+            connect_timeout: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(30),
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+
+    /// Starts on this endpoint instead of the first one, e.g. the last endpoint known to be
+    /// healthy. Ignored when it is not part of the endpoint list.
+    pub fn preferred_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.preferred_endpoint = Some(endpoint.into());
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn build(self) -> Result<CloudApiClient, CloudApiError> {
+        if self.endpoints.is_empty() {
+            return Err(CloudApiError::ConnectionFailed("No endpoints configured".to_string()));
+        }
+
+        let client = Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .timeout(self.request_timeout)
+            .build()?;
+
+        let active = self.preferred_endpoint
+            .and_then(|preferred| self.endpoints.iter().position(|endpoint| *endpoint == preferred))
+            .unwrap_or(0);
+
+        Ok(CloudApiClient {
+            endpoints: Arc::new(self.endpoints),
+            active: Arc::new(AtomicUsize::new(active)),
+            client,
+            retry_policy: self.retry_policy,
+            auth: Arc::new(Mutex::new(AuthState::default())),
+            refresh: Arc::new(Mutex::new(())),
+        })
+    }
+}
+
+impl CloudApiClient {
+    pub fn new(endpoint: impl Into<String>) -> Result<Self, CloudApiError> {
+        CloudApiClientBuilder::new(endpoint).build()
+    }
+
+    pub fn builder(endpoint: impl Into<String>) -> CloudApiClientBuilder {
+        CloudApiClientBuilder::new(endpoint)
+    }
+
+    pub fn endpoints(&self) -> &[String] {
+        &self.endpoints
+    }
+
+    /// The endpoint requests are currently sent to.
+    pub fn active_endpoint(&self) -> &str {
+        &self.endpoints[self.active.load(Ordering::Relaxed)]
+    }
+
+    /// Checks each endpoint in order, starting with the active one, and makes the first
+    /// one that answers the metadata route without a server error the active endpoint.
+    pub async fn probe(&self) -> Result<&str, CloudApiError> {
+        let start = self.active.load(Ordering::Relaxed);
+        let mut last_error = None;
+
+        for offset in 0..self.endpoints.len() {
+            let index = (start + offset) % self.endpoints.len();
+            let url = format!("{}{}", self.endpoints[index], METADATA_PATH);
+
+            match self.client.get(&url).header("Metadata", "true").send().await {
+                Ok(res) if !res.status().is_server_error() => {
+                    self.set_active(index);
+                    return Ok(self.active_endpoint());
+                }
+                Ok(res) => {
+                    tracing::warn!("Endpoint {} is unhealthy: {}", self.endpoints[index], res.status());
+                    last_error = Some(error_from_response(&url, res).await);
+                }
+                Err(e) => {
+                    tracing::warn!("Endpoint {} is unreachable: {}", self.endpoints[index], e);
+                    last_error = Some(CloudApiError::from(e));
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or(CloudApiError::ConnectionFailed("No endpoints configured".to_string())))
+    }
+
+    fn set_active(&self, index: usize) {
+        let previous = self.active.swap(index, Ordering::Relaxed);
+
+        if previous != index {
+            tracing::info!("Switched cloud-api endpoint from {} to {}", self.endpoints[previous], self.endpoints[index]);
         }
     }
 
-    fn get_metadata_url(&self) -> String {
-        format!("{}/cloud-api/v1/metadata", self.endpoint)
+    /// Registers this machine with the server and keeps the issued credential. Every
+    /// later call carries it as a bearer token, refreshing it before it expires and
+    /// registering again if the server no longer accepts it. The registration is kept
+    /// even when this call fails, so later calls retry it.
+    pub async fn register(&self, request: RegistrationRequest) -> Result<Credential, CloudApiError> {
+        let _refresh = self.refresh.lock().await;
+        self.auth.lock().await.registration = Some(request.clone());
+
+        let credential = self.request_credential(&request).await?;
+        self.auth.lock().await.credential = Some(credential.clone());
+
+        Ok(credential)
+    }
+
+    pub async fn credential(&self) -> Option<Credential> {
+        self.auth.lock().await.credential.clone()
+    }
+
+    async fn request_credential(&self, request: &RegistrationRequest) -> Result<Credential, CloudApiError> {
+        let res = self.send_to_endpoints(REGISTER_PATH, None, Delivery::AtMostOnce, |url| self.client.post(url).json(request)).await?;
+
+        Ok(read_json::<Credential>(res).await?.1)
+    }
+
+    /// Returns a bearer token that is not about to expire, or `None` when this client
+    /// has never registered. The auth state is not locked while a new credential is
+    /// obtained; a token that is due for refresh but still valid is used by other callers
+    /// in the meantime.
+    async fn bearer_token(&self, force_register: bool) -> Result<Option<String>, CloudApiError> {
+        let seen = {
+            let auth = self.auth.lock().await;
+
+            if auth.registration.is_none() {
+                return Ok(auth.credential.as_ref().map(|credential| credential.token.clone()));
+            }
+
+            match &auth.credential {
+                Some(credential) if !force_register && !credential.needs_refresh() => return Ok(Some(credential.token.clone())),
+                credential => credential.clone(),
+            }
+        };
+
+        let _refresh = match self.refresh.try_lock() {
+            Ok(refresh) => refresh,
+            Err(_) => {
+                if let Some(credential) = seen.as_ref().filter(|credential| !force_register && !credential.is_expired()) {
+                    return Ok(Some(credential.token.clone()));
+                }
+
+                self.refresh.lock().await
+            }
+        };
+
+        let (registration, current) = {
+            let auth = self.auth.lock().await;
+            (auth.registration.clone(), auth.credential.clone())
+        };
+
+        let Some(registration) = registration else {
+            return Ok(current.map(|credential| credential.token));
+        };
+
+        // Another caller may have obtained a new credential while this one waited.
+        if let Some(current) = current.as_ref().filter(|current| seen.as_ref().is_none_or(|seen| seen.token != current.token)) {
+            if !current.needs_refresh() {
+                return Ok(Some(current.token.clone()));
+            }
+        }
+
+        let credential = match current {
+            Some(credential) if !force_register && !credential.is_expired() => {
+                let refresh_path = format!("{}/refresh", REGISTER_PATH);
+
+                match self.send_to_endpoints(&refresh_path, Some(&credential.token), Delivery::AtMostOnce, |url| self.client.post(url)).await {
+                    Ok(res) => read_json::<Credential>(res).await?.1,
+                    Err(e) => {
+                        tracing::warn!("Failed to refresh credential, registering again: {}", e);
+                        self.request_credential(&registration).await?
+                    }
+                }
+            }
+            _ => self.request_credential(&registration).await?,
+        };
+
+        let token = credential.token.clone();
+        self.auth.lock().await.credential = Some(credential);
+
+        Ok(Some(token))
+    }
+
+    /// Reports the observed status of the registered virtual machine.
+    pub async fn report_status(&self, status: &VirtualMachineStatus) -> Result<(), CloudApiError> {
+        let resource = self.credential().await
+            .map(|credential| credential.resource)
+            .ok_or_else(|| CloudApiError::Unauthorized { status: 401, body: "Client is not registered".to_string() })?;
+
+        let path = format!("/cloud-api/v1/namespaces/{}/virtualmachines/{}/status", resource.namespace, resource.name);
+        self.send_idempotent(&path, |url| self.client.put(url).json(status)).await?;
+
+        Ok(())
+    }
+
+    /// Sends a liveness heartbeat for the registered virtual machine.
+    pub async fn send_heartbeat(&self, heartbeat: &AgentHeartbeat) -> Result<(), CloudApiError> {
+        let resource = self.credential().await
+            .map(|credential| credential.resource)
+            .ok_or_else(|| CloudApiError::Unauthorized { status: 401, body: "Client is not registered".to_string() })?;
+
+        let path = format!("/cloud-api/v1/namespaces/{}/virtualmachines/{}/heartbeat", resource.namespace, resource.name);
+        self.send_idempotent(&path, |url| self.client.put(url).json(heartbeat)).await?;
+
+        Ok(())
     }
 
     pub async fn get_metadata(&self) -> Result<MetadataResponse, CloudApiError> {
-        let url = format!("{}", self.get_metadata_url());
-        let res = self.client
-            .get(&url)
-            .header("Metadata", "true")
-            .send()
-            .await?
-            .error_for_status()? // HTTP-level error
-            .json::<MetadataResponse>()
-            .await?;
+        let res = self.send_idempotent(METADATA_PATH, |url| self.client.get(url)).await?;
 
-        Ok(res)
+        Ok(read_json::<MetadataResponse>(res).await?.1)
+    }
+
+    /// Fetches the user-data assigned to this machine, or `None` when it has none.
+    pub async fn get_user_data(&self) -> Result<Option<String>, CloudApiError> {
+        let path = format!("{}/user-data", METADATA_PATH);
+        let res = self.send_idempotent(&path, |url| self.client.get(url)).await?;
+
+        if res.status() == StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+
+        Ok(Some(res.text().await?))
+    }
+
+    /// Fetches the maintenance events scheduled for this machine.
+    pub async fn get_scheduled_events(&self) -> Result<ScheduledEvents, CloudApiError> {
+        let path = format!("{}/scheduled-events", METADATA_PATH);
+        let res = self.send_idempotent(&path, |url| self.client.get(url)).await?;
+
+        Ok(read_json::<ScheduledEvents>(res).await?.1)
+    }
+
+    /// Acknowledges scheduled events, allowing the platform to start them before their
+    /// `not_before` time. Acknowledging an event twice has no further effect.
+    pub async fn acknowledge_scheduled_events(&self, event_ids: Vec<String>) -> Result<(), CloudApiError> {
+        let path = format!("{}/scheduled-events/acknowledge", METADATA_PATH);
+        let request = AcknowledgeEventsRequest { event_ids };
+        self.send_idempotent(&path, |url| self.client.post(url).json(&request)).await?;
+
+        Ok(())
     }
 
     pub async fn get_extensions(&self) -> Result<Vec<ExtensionState>, CloudApiError> {
-        let url = format!("{}/extensions", self.get_metadata_url());
-        let res = self.client
-            .get(&url)
-            .header("Metadata", "true")
-            .send()
-            .await?
-            .error_for_status()?
-            .json::<Vec<ExtensionState>>()
-            .await?;
-
-        Ok(res)
+        Ok(self.poll_extensions().await?.extensions)
+    }
+
+    /// Fetches the assigned extensions along with the server's hint on whether more work
+    /// is pending (signalled through the `X-Cloud-Api-Pending-Work` response header).
+    pub async fn poll_extensions(&self) -> Result<ExtensionList, CloudApiError> {
+        let path = format!("{}/extensions", METADATA_PATH);
+        let res = self.send_idempotent(&path, |url| self.client.get(url)).await?;
+        let (headers, extensions) = read_json::<Vec<ExtensionState>>(res).await?;
+
+        let pending_work = headers
+            .get(PENDING_WORK_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+
+        Ok(ExtensionList { extensions, pending_work })
+    }
+
+    /// Sends an authenticated request that is safe to repeat. A 401 response is retried
+    /// once with a freshly registered credential.
+    async fn send_idempotent<F>(&self, path: &str, request: F) -> Result<Response, CloudApiError>
+    where
+        F: Fn(&str) -> RequestBuilder,
+    {
+        let token = self.bearer_token(false).await?;
+
+        match self.send_to_endpoints(path, token.as_deref(), Delivery::Idempotent, &request).await {
+            Err(CloudApiError::Unauthorized { status: 401, .. }) if self.auth.lock().await.registration.is_some() => {
+                let token = self.bearer_token(true).await?;
+                self.send_to_endpoints(path, token.as_deref(), Delivery::Idempotent, &request).await
+            }
+            result => result,
+        }
+    }
+
+    /// Sends a request to `path` on the active endpoint. When the endpoint stays
+    /// unreachable after retrying, the remaining endpoints are tried in order and the
+    /// first one that answers becomes the active endpoint. A request that is not safe to
+    /// repeat only moves on when it could not connect.
+    async fn send_to_endpoints<F>(&self, path: &str, token: Option<&str>, delivery: Delivery, request: F) -> Result<Response, CloudApiError>
+    where
+        F: Fn(&str) -> RequestBuilder,
+    {
+        let start = self.active.load(Ordering::Relaxed);
+        let mut last_error = None;
+
+        for offset in 0..self.endpoints.len() {
+            let index = (start + offset) % self.endpoints.len();
+            let url = format!("{}{}", self.endpoints[index], path);
+
+            match self.send_with_retries(&url, token, delivery, &request).await {
+                Ok(res) => {
+                    self.set_active(index);
+                    return Ok(res);
+                }
+                Err(e) if may_resend(delivery, &e) && is_unreachable(&e) => {
+                    tracing::warn!("Endpoint {} is unreachable: {}", self.endpoints[index], e);
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_error.unwrap_or(CloudApiError::ConnectionFailed("No endpoints configured".to_string())))
+    }
+
+    /// Retries connection errors, throttling and server errors according to the retry
+    /// policy; a request that is not safe to repeat is only retried when it could not
+    /// connect. A `Retry-After` longer than the policy's maximum backoff is returned to
+    /// the caller instead of being slept on.
+    async fn send_with_retries<F>(&self, url: &str, token: Option<&str>, delivery: Delivery, request: &F) -> Result<Response, CloudApiError>
+    where
+        F: Fn(&str) -> RequestBuilder,
+    {
+        let mut attempt = 0;
+
+        loop {
+            let mut builder = request(url).header("Metadata", "true");
+
+            if let Some(token) = token {
+                builder = builder.bearer_auth(token);
+            }
+
+            let error = match builder.send().await {
+                Ok(res) if res.status().is_success() => return Ok(res),
+                Ok(res) => error_from_response(url, res).await,
+                Err(e) => CloudApiError::from(e),
+            };
+
+            if attempt >= self.retry_policy.max_retries || !error.is_retryable() || !may_resend(delivery, &error) {
+                return Err(error);
+            }
+
+            let delay = match error.retry_after() {
+                Some(retry_after) if retry_after > self.retry_policy.max_backoff => return Err(error),
+                Some(retry_after) => retry_after,
+                None => self.retry_policy.backoff(attempt),
+            };
+
+            tracing::debug!("Request to {} failed ({}), retrying in {:?}", url, error, delay);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Whether a request that failed with `error` may be sent again. One that is not safe to
+/// repeat may only be resent when the server cannot have received it.
+fn may_resend(delivery: Delivery, error: &CloudApiError) -> bool {
+    match delivery {
+        Delivery::Idempotent => true,
+        Delivery::AtMostOnce => matches!(error, CloudApiError::Http(e) if e.is_connect()) || matches!(error, CloudApiError::ConnectionFailed(_)),
+    }
+}
+
+fn is_unreachable(error: &CloudApiError) -> bool {
+    match error {
+        CloudApiError::Http(e) => e.is_connect() || e.is_timeout(),
+        CloudApiError::ConnectionFailed(_) => true,
+        _ => false,
+    }
+}
+
+async fn read_json<T: DeserializeOwned>(res: Response) -> Result<(HeaderMap, T), CloudApiError> {
+    let headers = res.headers().clone();
+    let body = res.text().await?;
+
+    match serde_json::from_str::<T>(&body) {
+        Ok(value) => Ok((headers, value)),
+        Err(source) => Err(CloudApiError::Deserialization { source, body }),
+    }
+}
+
+async fn error_from_response(url: &str, res: Response) -> CloudApiError {
+    let status = res.status();
+    let retry_after = parse_retry_after(res.headers());
+    let body = res.text().await.unwrap_or_default();
+
+    match status {
+        StatusCode::NOT_FOUND => CloudApiError::NotFound { url: url.to_string(), body },
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => CloudApiError::Unauthorized { status: status.as_u16(), body },
+        StatusCode::TOO_MANY_REQUESTS => CloudApiError::Throttled { retry_after, body },
+        StatusCode::SERVICE_UNAVAILABLE if retry_after.is_some() => CloudApiError::Throttled { retry_after, body },
+        _ if status.is_server_error() => CloudApiError::ServerError { status: status.as_u16(), body },
+        _ => CloudApiError::UnexpectedStatus { status: status.as_u16(), body },
+    }
+}
+
+/// Parses `Retry-After` as either delay-seconds or an HTTP date.
+fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?.with_timezone(&Utc);
+    Some((date - Utc::now()).to_std().unwrap_or(Duration::ZERO))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderValue, RETRY_AFTER};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// Serves every request with `status` and an empty JSON list, returning the base URL.
+    fn serve(status: &'static str) -> String {
+        serve_counted(status).0
+    }
+
+    /// Like `serve`, also returning the number of requests served so far.
+    fn serve_counted(status: &'static str) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let served = Arc::new(AtomicUsize::new(0));
+        let counter = served.clone();
+
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+
+                // Only the headers are read; a request body may arrive in the same read.
+                while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                    match stream.read(&mut buffer) {
+                        Ok(0) | Err(_) => break,
+                        Ok(read) => request.extend_from_slice(&buffer[..read]),
+                    }
+                }
+
+                counter.fetch_add(1, Ordering::SeqCst);
+                let _ = write!(stream, "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: 2\r\nConnection: close\r\n\r\n[]", status);
+            }
+        });
+
+        (url, served)
+    }
+
+    /// A URL nothing listens on, so connecting to it is refused.
+    fn unreachable() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    }
+
+    fn client(endpoints: Vec<String>) -> CloudApiClient {
+        CloudApiClientBuilder::with_endpoints(endpoints)
+            .retry_policy(RetryPolicy::none())
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn fails_over_to_the_next_reachable_endpoint() {
+        let live = serve("200 OK");
+        let client = client(vec![unreachable(), live.clone()]);
+
+        client.poll_extensions().await.unwrap();
+
+        assert_eq!(client.active_endpoint(), live);
+    }
+
+    #[tokio::test]
+    async fn does_not_fail_over_on_error_responses() {
+        let first = serve("404 Not Found");
+        let client = client(vec![first.clone(), serve("200 OK")]);
+
+        let result = client.poll_extensions().await;
+
+        assert!(matches!(result, Err(CloudApiError::NotFound { .. })), "{:?}", result);
+        assert_eq!(client.active_endpoint(), first);
+    }
+
+    fn retrying_client(endpoints: Vec<String>) -> CloudApiClient {
+        CloudApiClientBuilder::with_endpoints(endpoints)
+            .retry_policy(RetryPolicy { max_retries: 2, initial_backoff: Duration::from_millis(1), max_backoff: Duration::from_millis(1) })
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn retries_idempotent_requests_on_server_errors() {
+        let (url, served) = serve_counted("503 Service Unavailable");
+
+        let result = retrying_client(vec![url]).poll_extensions().await;
+
+        assert!(matches!(result, Err(CloudApiError::ServerError { status: 503, .. })), "{:?}", result);
+        assert_eq!(served.load(Ordering::SeqCst), 3);
+    }
+
+    fn registration() -> RegistrationRequest {
+        RegistrationRequest { resource: None, registration_secret: None }
+    }
+
+    #[tokio::test]
+    async fn sends_a_registration_once_when_the_server_fails() {
+        let (url, served) = serve_counted("503 Service Unavailable");
+
+        let result = retrying_client(vec![url]).register(registration()).await;
+
+        assert!(matches!(result, Err(CloudApiError::ServerError { status: 503, .. })), "{:?}", result);
+        assert_eq!(served.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn moves_a_registration_on_to_the_next_endpoint_only_when_it_could_not_connect() {
+        let (live, served) = serve_counted("503 Service Unavailable");
+        let client = retrying_client(vec![unreachable(), live.clone()]);
+
+        assert!(client.register(registration()).await.is_err());
+
+        assert_eq!(served.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn starts_on_the_preferred_endpoint_when_it_is_configured() {
+        let endpoints = vec!["http://first".to_string(), "http://second".to_string()];
+
+        let preferred = CloudApiClientBuilder::with_endpoints(endpoints.clone()).preferred_endpoint("http://second").build().unwrap();
+        assert_eq!(preferred.active_endpoint(), "http://second");
+
+        let unknown = CloudApiClientBuilder::with_endpoints(endpoints).preferred_endpoint("http://other").build().unwrap();
+        assert_eq!(unknown.active_endpoint(), "http://first");
+    }
+
+    #[test]
+    fn requires_an_endpoint() {
+        assert!(CloudApiClientBuilder::with_endpoints(Vec::new()).build().is_err());
+    }
+
+    fn headers(retry_after: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(retry_after).unwrap());
+        headers
+    }
+
+    #[test]
+    fn parses_retry_after_seconds() {
+        assert_eq!(parse_retry_after(&headers("120")), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(&headers(" 5 ")), Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn parses_retry_after_http_dates() {
+        let in_a_minute = (Utc::now() + chrono::Duration::seconds(60)).to_rfc2822();
+        let delay = parse_retry_after(&headers(&in_a_minute)).unwrap();
+        assert!(delay > Duration::from_secs(55) && delay <= Duration::from_secs(60), "{:?}", delay);
+
+        assert_eq!(parse_retry_after(&headers("Wed, 21 Oct 2015 07:28:00 GMT")), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn ignores_missing_or_invalid_retry_after() {
+        assert_eq!(parse_retry_after(&HeaderMap::new()), None);
+        assert_eq!(parse_retry_after(&headers("soon")), None);
+        assert_eq!(parse_retry_after(&headers("-1")), None);
     }
 }
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -10,4 +12,84 @@ pub enum CloudApiError {
 
     #[error("Connection failed: {0}")]
     ConnectionFailed(String),
+
+    #[error("Resource not found: {url}")]
+    NotFound { url: String, body: String },
+
+    #[error("Unauthorized ({status}): {body}")]
+    Unauthorized { status: u16, body: String },
+
+    #[error("Throttled by server (retry after {retry_after:?}): {body}")]
+    Throttled { retry_after: Option<Duration>, body: String },
+
+    #[error("Server error ({status}): {body}")]
+    ServerError { status: u16, body: String },
+
+    #[error("Unexpected response ({status}): {body}")]
+    UnexpectedStatus { status: u16, body: String },
+
+    #[error("Failed to deserialize response: {source}")]
+    Deserialization {
+        #[source]
+        source: serde_json::Error,
+        body: String,
+    },
+}
+
+impl CloudApiError {
+    /// Whether the request may succeed if sent again unchanged.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            CloudApiError::Http(e) => e.is_connect() || e.is_timeout(),
+            CloudApiError::ConnectionFailed(_) => true,
+            CloudApiError::Throttled { .. } => true,
+            CloudApiError::ServerError { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// The delay requested by the server through `Retry-After`, if any.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            CloudApiError::Throttled { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// The response body attached to the error, if the server sent one.
+    pub fn body(&self) -> Option<&str> {
+        match self {
+            CloudApiError::NotFound { body, .. }
+            | CloudApiError::Unauthorized { body, .. }
+            | CloudApiError::Throttled { body, .. }
+            | CloudApiError::ServerError { body, .. }
+            | CloudApiError::UnexpectedStatus { body, .. }
+            | CloudApiError::Deserialization { body, .. } => Some(body),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_transient_errors_only() {
+        assert!(CloudApiError::ConnectionFailed("refused".to_string()).is_retryable());
+        assert!(CloudApiError::Throttled { retry_after: None, body: String::new() }.is_retryable());
+        assert!(CloudApiError::ServerError { status: 502, body: String::new() }.is_retryable());
+
+        assert!(!CloudApiError::NotFound { url: "http://example".to_string(), body: String::new() }.is_retryable());
+        assert!(!CloudApiError::Unauthorized { status: 401, body: String::new() }.is_retryable());
+        assert!(!CloudApiError::UnexpectedStatus { status: 400, body: String::new() }.is_retryable());
+    }
+
+    #[test]
+    fn only_throttling_carries_a_retry_after() {
+        let retry_after = Some(Duration::from_secs(3));
+
+        assert_eq!(CloudApiError::Throttled { retry_after, body: String::new() }.retry_after(), retry_after);
+        assert_eq!(CloudApiError::ServerError { status: 503, body: String::new() }.retry_after(), None);
+    }
 }
@@ -1,3 +1,4 @@
 pub mod client;
 pub mod model;
-pub mod error;
\ No newline at end of file
+pub mod error;
+pub mod retry;
\ No newline at end of file
@@ -0,0 +1,68 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::resource::ResourceRef;
+
+/// Sent by an agent to obtain credentials for the virtual machine it runs on.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RegistrationRequest {
+    /// The virtual machine to register as. When omitted the server resolves it from the
+    /// caller's address.
+    pub resource: Option<ResourceRef>,
+    /// The named virtual machine's own secret when the server binds callers at
+    /// registration; otherwise the shared bootstrap secret, when the server has one.
+    pub registration_secret: Option<String>,
+}
+
+/// A bearer token bound to a single virtual machine.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Credential {
+    pub token: String,
+    pub resource: ResourceRef,
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Credential {
+    /// Whether the credential is past, or within the last fifth of, its lifetime.
+    pub fn needs_refresh(&self) -> bool {
+        let lifetime = self.expires_at - self.issued_at;
+        Utc::now() >= self.expires_at - lifetime / 5
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Utc::now() >= self.expires_at
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    /// A credential with a 100 minute lifetime, `elapsed_mins` of which have passed.
+    fn credential(elapsed_mins: i64) -> Credential {
+        let issued_at = Utc::now() - Duration::minutes(elapsed_mins);
+
+        Credential {
+            token: "token".to_string(),
+            resource: ResourceRef::new("default", "vm-1"),
+            issued_at,
+            expires_at: issued_at + Duration::minutes(100),
+        }
+    }
+
+    #[test]
+    fn refreshes_in_the_last_fifth_of_the_lifetime() {
+        assert!(!credential(0).needs_refresh());
+        assert!(!credential(79).needs_refresh());
+        assert!(credential(81).needs_refresh());
+        assert!(credential(120).needs_refresh());
+    }
+
+    #[test]
+    fn expires_at_the_end_of_the_lifetime() {
+        assert!(!credential(99).is_expired());
+        assert!(credential(100).is_expired());
+    }
+}
@@ -1,10 +1,88 @@
-use serde::Deserialize;
+use std::collections::BTreeMap;
 
-#[derive(Debug, Deserialize)]
+use serde::{Deserialize, Serialize};
+
+/// Instance metadata for the calling virtual machine. Fields added after the first
+/// release default when absent, and fields this version does not know about are kept in
+/// `additional_properties` so newer servers do not break older clients.
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MetadataResponse {
     pub instance_id: String,
     pub location: String,
     pub name: String,
     pub os_type: String,
     pub zone: Option<String>,
-}
\ No newline at end of file
+    #[serde(default)]
+    pub vm_size: Option<String>,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub network_interfaces: Vec<NetworkInterface>,
+    #[serde(default)]
+    pub image_reference: Option<ImageReference>,
+    #[serde(default)]
+    pub disks: Vec<Disk>,
+    #[serde(default)]
+    pub user_data: Option<String>,
+    #[serde(flatten)]
+    pub additional_properties: BTreeMap<String, serde_json::Value>,
+}
+
+/// Also read from camelCase VirtualMachine manifests, so multi-word fields accept their
+/// camelCase spelling; they are always written in snake_case.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct NetworkInterface {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default, alias = "macAddress")]
+    pub mac_address: Option<String>,
+    #[serde(default, alias = "ipAddresses")]
+    pub ip_addresses: Vec<IpAddressInfo>,
+    #[serde(flatten)]
+    pub additional_properties: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct IpAddressInfo {
+    pub address: String,
+    /// Subnet in CIDR notation, e.g. `10.0.0.0/24`.
+    #[serde(default)]
+    pub subnet: Option<String>,
+    #[serde(default)]
+    pub gateway: Option<String>,
+    #[serde(default, alias = "publicAddress")]
+    pub public_address: Option<String>,
+    #[serde(flatten)]
+    pub additional_properties: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ImageReference {
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub publisher: Option<String>,
+    #[serde(default)]
+    pub offer: Option<String>,
+    #[serde(default)]
+    pub sku: Option<String>,
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(flatten)]
+    pub additional_properties: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Disk {
+    pub name: String,
+    #[serde(default)]
+    pub lun: Option<u32>,
+    #[serde(default, alias = "sizeGb")]
+    pub size_gb: Option<u64>,
+    #[serde(default, alias = "isOsDisk")]
+    pub is_os_disk: bool,
+    #[serde(flatten)]
+    pub additional_properties: BTreeMap<String, serde_json::Value>,
+}
@@ -0,0 +1,34 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduledEventType {
+    Reboot,
+    Redeploy,
+    Freeze,
+    Terminate,
+}
+
+/// Platform maintenance planned for a virtual machine. The platform will not start it
+/// before `not_before` unless every party that cares has acknowledged it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ScheduledEvent {
+    pub event_id: String,
+    pub event_type: ScheduledEventType,
+    pub not_before: DateTime<Utc>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub acknowledged_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ScheduledEvents {
+    pub events: Vec<ScheduledEvent>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AcknowledgeEventsRequest {
+    pub event_ids: Vec<String>,
+}
@@ -13,7 +13,7 @@ pub enum ExtensionStatus {
     Disabled,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ExtensionState {
   pub uid: String,
   pub id: String,
@@ -22,6 +22,18 @@ pub struct ExtensionState {
   pub config: Option<String>,
   pub status: ExtensionStatus,
   pub modified_at: String,
+  /// Hex SHA-256 of the extension package, delivered with the assignment so the agent can
+  /// verify a download without trusting the package endpoint.
+  #[serde(default, alias = "packageSha256", skip_serializing_if = "Option::is_none")]
+  pub package_sha256: Option<String>,
+}
+
+/// Result of polling the extension assignments for this machine.
+#[derive(Debug)]
+pub struct ExtensionList {
+    pub extensions: Vec<ExtensionState>,
+    /// The server has work queued for this machine and wants it to poll again soon.
+    pub pending_work: bool,
 }
 
 impl  ExtensionState {
@@ -34,6 +46,7 @@ impl  ExtensionState {
             modified_at: Utc::now().to_rfc3339(),
             config: None,
             status: ExtensionStatus::NotInstalled,
+            package_sha256: None,
         }
     }
 
@@ -52,6 +65,6 @@ impl  ExtensionState {
     pub fn get_package_id(&self) -> String {
         let package_id: String = format!("{}-{}", self.publisher.as_ref().unwrap_or(&"none".to_string()), self.id);
 
-        return package_id;
+        package_id
     }
 }
\ No newline at end of file
@@ -0,0 +1,74 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::extension::ExtensionStatus;
+use super::hook::HookRunSummary;
+
+/// Periodic liveness report sent by the agent running on a virtual machine.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AgentHeartbeat {
+    pub agent_version: String,
+    pub sent_at: DateTime<Utc>,
+    pub started_at: DateTime<Utc>,
+    pub uptime_secs: u64,
+    pub os: OsInfo,
+    #[serde(default)]
+    pub last_reconcile: Option<ReconcileSummary>,
+    #[serde(default)]
+    pub extensions: Vec<ExtensionSummary>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OsInfo {
+    pub family: String,
+    pub arch: String,
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub hostname: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReconcileResult {
+    Succeeded,
+    /// Reconciliation ran but at least one extension failed.
+    Degraded,
+    /// The endpoint could not be reached and the cached desired state was used.
+    EndpointUnreachable,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReconcileSummary {
+    pub completed_at: DateTime<Utc>,
+    pub result: ReconcileResult,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExtensionSummary {
+    pub package_id: String,
+    pub version: String,
+    pub status: ExtensionStatus,
+    #[serde(default)]
+    pub last_error: Option<String>,
+    /// The most recent hook execution of the extension.
+    #[serde(default)]
+    pub last_run: Option<HookRunSummary>,
+}
+
+/// Whether the agent on a virtual machine is sending heartbeats.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentState {
+    Ready,
+    NotReady,
+}
+
+/// The agent's health as recorded by the server from its heartbeats.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AgentStatus {
+    pub state: AgentState,
+    /// Server time at which the last heartbeat was received.
+    pub last_heartbeat_at: DateTime<Utc>,
+    pub heartbeat: AgentHeartbeat,
+}
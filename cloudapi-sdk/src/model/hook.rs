@@ -0,0 +1,58 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// A script hook defined by an extension.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum HookKind {
+    Install,
+    Uninstall,
+    EventHandler,
+}
+
+impl HookKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HookKind::Install => "install",
+            HookKind::Uninstall => "uninstall",
+            HookKind::EventHandler => "event_handler",
+        }
+    }
+}
+
+/// Why the agent ran a hook.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunTrigger {
+    /// Reconciling the extension against its assignment.
+    Reconcile,
+    /// Requested by an operator through the agent CLI or control API.
+    Rerun,
+    /// The extension is no longer assigned to the virtual machine.
+    Unassigned,
+    /// An extension directory was found that the agent does not track.
+    Orphaned,
+    /// Delivering a scheduled event the extension subscribes to.
+    ScheduledEvent,
+    /// The agent is being uninstalled with its data.
+    Purge,
+}
+
+/// Outcome of a single hook execution. The hook's output stays on the virtual machine.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HookRunSummary {
+    pub hook: HookKind,
+    pub version: String,
+    pub trigger: RunTrigger,
+    /// Further detail on the trigger, such as the scheduled event id.
+    #[serde(default)]
+    pub detail: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub duration_ms: u64,
+    /// Missing when the hook could not be started or was killed.
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
@@ -1,2 +1,8 @@
 pub mod extension;
-pub mod compute;
\ No newline at end of file
+pub mod compute;
+pub mod resource;
+pub mod auth;
+pub mod status;
+pub mod events;
+pub mod heartbeat;
+pub mod hook;
@@ -0,0 +1,25 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Identifies a namespaced resource on the server, e.g. a `VirtualMachine`.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+pub struct ResourceRef {
+    pub namespace: String,
+    pub name: String,
+}
+
+impl ResourceRef {
+    pub fn new(namespace: &str, name: &str) -> Self {
+        ResourceRef {
+            namespace: namespace.to_string(),
+            name: name.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ResourceRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.namespace, self.name)
+    }
+}
@@ -0,0 +1,15 @@
+use serde::{Deserialize, Serialize};
+
+use super::extension::ExtensionState;
+use super::heartbeat::AgentStatus;
+
+/// Status of a virtual machine as reported by its agent.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct VirtualMachineStatus {
+    pub reported_at: Option<String>,
+    #[serde(default)]
+    pub extensions: Vec<ExtensionState>,
+    /// Recorded by the server from heartbeats; agents leave this unset when reporting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<AgentStatus>,
+}
@@ -0,0 +1,61 @@
+use actix_web::{web, HttpResponse};
+use serde::Serialize;
+use serde_json::json;
+use std::path::Path;
+
+use crate::state::AppState;
+
+#[derive(Debug, Serialize)]
+struct ReadinessCheck {
+    name: &'static str,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl ReadinessCheck {
+    fn new(name: &'static str, result: Result<(), String>) -> Self {
+        ReadinessCheck { name, ok: result.is_ok(), error: result.err() }
+    }
+}
+
+/**
+ * Liveness: the server is up and handling requests.
+ */
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+/**
+ * Readiness: the resource store is usable and the directories backing it can be read.
+ * Responds 503 with the failing checks otherwise.
+ */
+pub async fn readyz(state: web::Data<AppState>) -> HttpResponse {
+    let mut checks = vec![
+        ReadinessCheck::new("resource_store", match state.store.is_available() {
+            true => Ok(()),
+            false => Err("Resource store is poisoned".to_string()),
+        }),
+        ReadinessCheck::new("resource_dir", check_dir(&state.config.resource_dir)),
+    ];
+
+    if let Some(package_dir) = &state.config.package_dir {
+        checks.push(ReadinessCheck::new("package_dir", check_dir(package_dir)));
+    }
+
+    let ready = checks.iter().all(|check| check.ok);
+    let body = json!({ "status": if ready { "ready" } else { "not_ready" }, "checks": checks });
+
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        tracing::warn!("Not ready: {}", body);
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+fn check_dir(dir: &str) -> Result<(), String> {
+    std::fs::read_dir(Path::new(dir))
+        .map(|_| ())
+        .map_err(|e| format!("Failed to read {}: {}", dir, e))
+}
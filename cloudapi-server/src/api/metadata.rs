@@ -0,0 +1,46 @@
+use actix_web::{web, HttpResponse};
+use cloudapi_sdk::model::events::{AcknowledgeEventsRequest, ScheduledEvents};
+
+use crate::api::error::ApiError;
+use crate::constants;
+use crate::identity::CallingVm;
+use crate::state::AppState;
+
+pub async fn get_metadata(caller: CallingVm) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(caller.vm.to_metadata()))
+}
+
+pub async fn get_extensions(caller: CallingVm) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok()
+        .insert_header((constants::PENDING_WORK_HEADER, caller.vm.has_pending_work().to_string()))
+        .json(&caller.vm.extensions))
+}
+
+pub async fn get_user_data(caller: CallingVm) -> Result<HttpResponse, ApiError> {
+    match caller.vm.user_data {
+        Some(user_data) => Ok(HttpResponse::Ok().content_type("text/plain; charset=utf-8").body(user_data)),
+        None => Ok(HttpResponse::NoContent().finish()),
+    }
+}
+
+pub async fn get_scheduled_events(state: web::Data<AppState>, caller: CallingVm) -> Result<HttpResponse, ApiError> {
+    let events = state.store.list_scheduled_events(&caller.vm.resource_ref())
+        .iter()
+        .map(|event| event.to_event())
+        .collect();
+
+    Ok(HttpResponse::Ok().json(ScheduledEvents { events }))
+}
+
+pub async fn acknowledge_scheduled_events(state: web::Data<AppState>, caller: CallingVm, request: web::Json<AcknowledgeEventsRequest>) -> Result<HttpResponse, ApiError> {
+    let vm = caller.vm.resource_ref();
+    let acknowledged = state.store.acknowledge_scheduled_events(&vm, &request.event_ids);
+
+    if acknowledged < request.event_ids.len() {
+        return Err(ApiError::NotFound(format!("Not all scheduled events were found for {}", vm)));
+    }
+
+    tracing::info!("{} acknowledged scheduled events {:?}", vm, request.event_ids);
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,24 @@
+use actix_web::{web, HttpResponse};
+use std::path::Path;
+
+use crate::api::error::ApiError;
+use crate::metrics;
+use crate::state::AppState;
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+pub async fn get_metrics(state: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    if let Some(package_dir) = state.config.package_dir.clone() {
+        // Walking the repository is blocking filesystem work, so keep it off the worker.
+        match web::block(move || metrics::package_repository_size(Path::new(&package_dir))).await {
+            Ok(Ok((packages, bytes))) => state.metrics.set_package_repository_size(packages, bytes),
+            Ok(Err(e)) => tracing::warn!("Failed to measure package repository: {:?}", e),
+            Err(e) => tracing::warn!("Failed to measure package repository: {:?}", e),
+        }
+    }
+
+    let body = state.metrics.encode(&state.store)
+        .map_err(|e| ApiError::Internal(format!("Failed to encode metrics: {}", e)))?;
+
+    Ok(HttpResponse::Ok().content_type(CONTENT_TYPE).body(body))
+}
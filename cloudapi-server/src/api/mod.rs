@@ -0,0 +1,37 @@
+pub mod error;
+mod health;
+mod metadata;
+mod metrics;
+mod register;
+mod virtual_machine;
+
+use actix_web::web;
+
+use crate::middleware::metadata_guard::MetadataGuard;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/healthz", web::get().to(health::healthz))
+        .route("/readyz", web::get().to(health::readyz));
+
+    cfg.service(
+        web::scope("/cloud-api/v1")
+            .wrap(MetadataGuard)
+            .route("/register", web::post().to(register::register))
+            .route("/register/refresh", web::post().to(register::refresh))
+            .route("/metadata", web::get().to(metadata::get_metadata))
+            .route("/metadata/extensions", web::get().to(metadata::get_extensions))
+            .route("/metadata/user-data", web::get().to(metadata::get_user_data))
+            .route("/metadata/scheduled-events", web::get().to(metadata::get_scheduled_events))
+            .route("/metadata/scheduled-events/acknowledge", web::post().to(metadata::acknowledge_scheduled_events))
+            .route("/namespaces/{namespace}/virtualmachines/{name}/extensions", web::get().to(virtual_machine::get_extensions))
+            .route("/namespaces/{namespace}/virtualmachines/{name}/status", web::put().to(virtual_machine::put_status))
+            .route("/namespaces/{namespace}/virtualmachines/{name}/heartbeat", web::put().to(virtual_machine::put_heartbeat))
+    );
+}
+
+/**
+ * Routes served on the admin listener only.
+ */
+pub fn configure_admin(cfg: &mut web::ServiceConfig) {
+    cfg.route("/metrics", web::get().to(metrics::get_metrics));
+}
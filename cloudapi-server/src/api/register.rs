@@ -0,0 +1,147 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use cloudapi_sdk::model::auth::RegistrationRequest;
+
+use crate::api::error::ApiError;
+use crate::auth::{self, AuthenticatedVm};
+use crate::identity::VmResolution;
+use crate::middleware::metadata_guard::CallerAddress;
+use crate::state::AppState;
+
+/**
+ * Issues a credential bound to the caller's VirtualMachine. In `registration` mode the
+ * caller names its VirtualMachine, proves it with that VirtualMachine's registration
+ * secret and its address is bound to it; otherwise the VirtualMachine is resolved from
+ * the caller's address and a named resource must match.
+ */
+pub async fn register(state: web::Data<AppState>, caller: CallerAddress, request: web::Json<RegistrationRequest>) -> Result<HttpResponse, ApiError> {
+    let request = request.into_inner();
+
+    let resource = if state.resolver.mode() == VmResolution::Registration {
+        let resource = request.resource
+            .ok_or_else(|| ApiError::BadRequest("Registration must name a VirtualMachine".to_string()))?;
+
+        let vm = state.store.get_virtual_machine(&resource)
+            .ok_or_else(|| ApiError::NotFound(format!("VirtualMachine {} not found", resource)))?;
+
+        if !request.registration_secret.as_deref().is_some_and(|secret| vm.has_registration_secret(secret)) {
+            tracing::warn!("Rejected registration of {} from {}: invalid registration secret", resource, caller.0);
+            return Err(ApiError::Unauthorized(format!("Invalid registration secret for {}", resource)));
+        }
+
+        state.resolver.bind(caller.0, resource.clone())?;
+        resource
+    } else {
+        if let Some(secret) = &state.config.registration_secret {
+            if request.registration_secret.as_ref() != Some(secret) {
+                tracing::warn!("Rejected registration from {}: invalid registration secret", caller.0);
+                return Err(ApiError::Unauthorized("Invalid registration secret".to_string()));
+            }
+        }
+
+        let resolved = state.resolver.resolve(&state.store, caller.0)
+            .ok_or_else(|| ApiError::NotFound(format!("No VirtualMachine is registered for caller address {}", caller.0)))?
+            .resource_ref();
+
+        if let Some(resource) = request.resource.filter(|resource| *resource != resolved) {
+            return Err(ApiError::Forbidden(format!("Caller {} is {}, not {}", caller.0, resolved, resource)));
+        }
+
+        resolved
+    };
+
+    let credential = state.tokens.issue(resource);
+    tracing::info!("Issued credential for {} to {} (expires {})", credential.resource, caller.0, credential.expires_at);
+
+    Ok(HttpResponse::Ok().json(credential))
+}
+
+/**
+ * Exchanges a valid credential for a new one and revokes the old token.
+ */
+pub async fn refresh(state: web::Data<AppState>, vm: AuthenticatedVm, req: HttpRequest) -> Result<HttpResponse, ApiError> {
+    let credential = state.tokens.issue(vm.resource().clone());
+
+    if let Some(token) = auth::bearer_token(&req) {
+        state.tokens.revoke(token);
+    }
+
+    Ok(HttpResponse::Ok().json(credential))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::TokenStore;
+    use crate::config::ServerConfig;
+    use crate::identity::VmResolver;
+    use crate::middleware::metadata_guard::{MetadataGuard, METADATA_HEADER};
+    use crate::resource::ResourceStore;
+    use actix_web::{http::StatusCode, test, App};
+    use serde_json::json;
+
+    // SHA-256 of "vm-1-secret".
+    const VM_SECRET_SHA256: &str = "5608bb51e1c3c3604190c02ee2f8b6cb2fb34b94fda366206a6ba6f540822be8";
+
+    macro_rules! registration_app {
+        () => {{
+            let store = ResourceStore::default();
+
+            store.put_virtual_machine(serde_json::from_value(json!({
+                "apiVersion": "api.cloud-api.dev/v1alpha1",
+                "kind": "VirtualMachine",
+                "metadata": { "name": "vm-1", "namespace": "default" },
+                "registrationSecretSha256": VM_SECRET_SHA256
+            })).unwrap());
+
+            test::init_service(
+                App::new()
+                    .app_data(web::Data::new(AppState {
+                        config: ServerConfig { registration_secret: Some("fleet-secret".to_string()), ..ServerConfig::default() },
+                        store,
+                        tokens: TokenStore::new(3600),
+                        resolver: VmResolver::new(VmResolution::Registration),
+                        metrics: Default::default(),
+                    }))
+                    .wrap(MetadataGuard)
+                    .route("/register", web::post().to(register))
+            ).await
+        }};
+    }
+
+    fn register_request(peer: &str, secret: &str) -> test::TestRequest {
+        test::TestRequest::post().uri("/register")
+            .insert_header((METADATA_HEADER, "true"))
+            .peer_addr(peer.parse().unwrap())
+            .set_json(json!({
+                "resource": { "namespace": "default", "name": "vm-1" },
+                "registration_secret": secret
+            }))
+    }
+
+    #[actix_web::test]
+    async fn registers_with_the_vms_own_secret() {
+        let app = registration_app!();
+
+        let res = test::call_service(&app, register_request("10.0.0.4:50000", "vm-1-secret").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn rejects_the_fleet_secret_in_registration_mode() {
+        let app = registration_app!();
+
+        let res = test::call_service(&app, register_request("10.0.0.4:50000", "fleet-secret").to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn rejects_taking_over_a_bound_vm() {
+        let app = registration_app!();
+
+        let res = test::call_service(&app, register_request("10.0.0.4:50000", "vm-1-secret").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = test::call_service(&app, register_request("10.0.0.9:50000", "vm-1-secret").to_request()).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+    }
+}
@@ -0,0 +1,50 @@
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use cloudapi_sdk::model::heartbeat::AgentHeartbeat;
+use cloudapi_sdk::model::resource::ResourceRef;
+use cloudapi_sdk::model::status::VirtualMachineStatus;
+
+use crate::api::error::ApiError;
+use crate::auth::AuthenticatedVm;
+use crate::state::AppState;
+
+pub async fn get_extensions(state: web::Data<AppState>, vm: AuthenticatedVm, path: web::Path<(String, String)>) -> Result<HttpResponse, ApiError> {
+    let (namespace, name) = path.into_inner();
+    let resource = ResourceRef { namespace, name };
+    vm.ensure_is(&resource)?;
+
+    let vm = state.store.get_virtual_machine(&resource)
+        .ok_or_else(|| ApiError::NotFound(format!("VirtualMachine {} not found", resource)))?;
+
+    Ok(HttpResponse::Ok().json(&vm.extensions))
+}
+
+pub async fn put_status(state: web::Data<AppState>, vm: AuthenticatedVm, path: web::Path<(String, String)>, status: web::Json<VirtualMachineStatus>) -> Result<HttpResponse, ApiError> {
+    let (namespace, name) = path.into_inner();
+    let resource = ResourceRef { namespace, name };
+    vm.ensure_is(&resource)?;
+
+    let mut status = status.into_inner();
+    status.reported_at.get_or_insert_with(|| Utc::now().to_rfc3339());
+
+    if !state.store.set_virtual_machine_status(&resource, status) {
+        return Err(ApiError::NotFound(format!("VirtualMachine {} not found", resource)));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub async fn put_heartbeat(state: web::Data<AppState>, vm: AuthenticatedVm, path: web::Path<(String, String)>, heartbeat: web::Json<AgentHeartbeat>) -> Result<HttpResponse, ApiError> {
+    let (namespace, name) = path.into_inner();
+    let resource = ResourceRef { namespace, name };
+    vm.ensure_is(&resource)?;
+
+    let heartbeat = heartbeat.into_inner();
+    tracing::debug!("Heartbeat from {} (agent {}, up {}s)", resource, heartbeat.agent_version, heartbeat.uptime_secs);
+
+    if !state.store.record_heartbeat(&resource, heartbeat) {
+        return Err(ApiError::NotFound(format!("VirtualMachine {} not found", resource)));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
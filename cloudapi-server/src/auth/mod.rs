@@ -0,0 +1,214 @@
+use actix_web::{dev::Payload, http::header, web, FromRequest, HttpMessage, HttpRequest};
+use chrono::{Duration, Utc};
+use cloudapi_sdk::model::auth::Credential;
+use cloudapi_sdk::model::resource::ResourceRef;
+use rand::RngCore;
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::sync::RwLock;
+
+use crate::api::error::ApiError;
+use crate::middleware::metadata_guard::CallerAddress;
+use crate::state::AppState;
+
+/**
+ * Issues and validates per-VM bearer tokens. Tokens only live in memory, so agents
+ * register again after a server restart.
+ */
+pub struct TokenStore {
+    ttl: Duration,
+    credentials: RwLock<HashMap<String, Credential>>,
+}
+
+impl TokenStore {
+    pub fn new(ttl_secs: u64) -> Self {
+        TokenStore {
+            ttl: Duration::seconds(ttl_secs as i64),
+            credentials: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn issue(&self, resource: ResourceRef) -> Credential {
+        let mut bytes = [0u8; 32];
+        rand::rng().fill_bytes(&mut bytes);
+
+        let issued_at = Utc::now();
+        let credential = Credential {
+            token: bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+            resource,
+            issued_at,
+            expires_at: issued_at + self.ttl,
+        };
+
+        let mut credentials = self.credentials.write().unwrap();
+        credentials.retain(|_, issued| !issued.is_expired());
+        credentials.insert(credential.token.clone(), credential.clone());
+
+        credential
+    }
+
+    pub fn validate(&self, token: &str) -> Option<Credential> {
+        self.credentials.read().unwrap()
+            .get(token)
+            .filter(|credential| !credential.is_expired())
+            .cloned()
+    }
+
+    pub fn revoke(&self, token: &str) {
+        self.credentials.write().unwrap().remove(token);
+    }
+}
+
+/**
+ * The virtual machine a request was authenticated as, taken from its bearer token. The
+ * token must be presented from an address that resolves to that same virtual machine.
+ */
+#[derive(Debug, Clone)]
+pub struct AuthenticatedVm {
+    pub credential: Credential,
+}
+
+impl AuthenticatedVm {
+    pub fn resource(&self) -> &ResourceRef {
+        &self.credential.resource
+    }
+
+    /**
+     * Rejects requests that name a resource other than the authenticated VM.
+     */
+    pub fn ensure_is(&self, resource: &ResourceRef) -> Result<(), ApiError> {
+        if self.resource() != resource {
+            return Err(ApiError::Forbidden(format!("Credential for {} cannot access {}", self.resource(), resource)));
+        }
+
+        Ok(())
+    }
+}
+
+impl FromRequest for AuthenticatedVm {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(authenticate(req))
+    }
+}
+
+pub fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+fn authenticate(req: &HttpRequest) -> Result<AuthenticatedVm, ApiError> {
+    let state = req.app_data::<web::Data<AppState>>()
+        .ok_or_else(|| ApiError::Internal("Application state not configured".to_string()))?;
+
+    let token = bearer_token(req)
+        .ok_or_else(|| ApiError::Unauthorized("Missing bearer token".to_string()))?;
+
+    let credential = state.tokens.validate(token)
+        .ok_or_else(|| ApiError::Unauthorized("Invalid or expired bearer token".to_string()))?;
+
+    let caller = req.extensions().get::<CallerAddress>().copied()
+        .ok_or_else(|| ApiError::Internal("Caller address not recorded".to_string()))?;
+
+    // A token is only accepted from the VirtualMachine it was issued to.
+    if state.resolver.resolve(&state.store, caller.0).is_none_or(|vm| vm.resource_ref() != credential.resource) {
+        return Err(ApiError::Forbidden(format!("Credential for {} cannot be used from {}", credential.resource, caller.0)));
+    }
+
+    Ok(AuthenticatedVm { credential })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ServerConfig;
+    use crate::identity::{VmResolution, VmResolver};
+    use crate::resource::ResourceStore;
+    use actix_web::test::TestRequest;
+    use serde_json::json;
+
+    #[test]
+    fn issued_credentials_validate_until_revoked() {
+        let tokens = TokenStore::new(3600);
+        let credential = tokens.issue(ResourceRef::new("default", "vm-1"));
+
+        assert_eq!(tokens.validate(&credential.token).unwrap().resource, ResourceRef::new("default", "vm-1"));
+        assert!(tokens.validate("unknown").is_none());
+
+        tokens.revoke(&credential.token);
+        assert!(tokens.validate(&credential.token).is_none());
+    }
+
+    #[test]
+    fn expired_credentials_do_not_validate() {
+        let tokens = TokenStore::new(0);
+        let credential = tokens.issue(ResourceRef::new("default", "vm-1"));
+
+        assert!(tokens.validate(&credential.token).is_none());
+    }
+
+    #[test]
+    fn tokens_are_unique() {
+        let tokens = TokenStore::new(3600);
+        let resource = ResourceRef::new("default", "vm-1");
+
+        assert_ne!(tokens.issue(resource.clone()).token, tokens.issue(resource).token);
+    }
+
+    #[test]
+    fn reads_bearer_tokens_only() {
+        let req = TestRequest::default().insert_header((header::AUTHORIZATION, "Bearer abc")).to_http_request();
+        assert_eq!(bearer_token(&req), Some("abc"));
+
+        let req = TestRequest::default().insert_header((header::AUTHORIZATION, "Basic abc")).to_http_request();
+        assert_eq!(bearer_token(&req), None);
+
+        assert_eq!(bearer_token(&TestRequest::default().to_http_request()), None);
+    }
+
+    fn state() -> web::Data<AppState> {
+        let store = ResourceStore::default();
+
+        for (name, address) in [("vm-1", "10.0.0.4"), ("vm-2", "10.0.0.5")] {
+            store.put_virtual_machine(serde_json::from_value(json!({
+                "apiVersion": "api.cloud-api.dev/v1alpha1",
+                "kind": "VirtualMachine",
+                "metadata": { "name": name, "namespace": "default" },
+                "networkInterfaces": [{ "ipAddresses": [{ "address": address }] }]
+            })).unwrap());
+        }
+
+        web::Data::new(AppState {
+            config: ServerConfig::default(),
+            store,
+            tokens: TokenStore::new(3600),
+            resolver: VmResolver::new(VmResolution::Address),
+            metrics: Default::default(),
+        })
+    }
+
+    fn authenticate_from(state: &web::Data<AppState>, token: &str, caller: &str) -> Result<AuthenticatedVm, ApiError> {
+        let req = TestRequest::default()
+            .app_data(state.clone())
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+            .to_http_request();
+        req.extensions_mut().insert(CallerAddress(caller.parse().unwrap()));
+
+        authenticate(&req)
+    }
+
+    #[test]
+    fn accepts_tokens_only_from_the_vm_they_were_issued_to() {
+        let state = state();
+        let credential = state.tokens.issue(ResourceRef::new("default", "vm-1"));
+
+        assert_eq!(authenticate_from(&state, &credential.token, "10.0.0.4").unwrap().resource(), &ResourceRef::new("default", "vm-1"));
+        assert!(matches!(authenticate_from(&state, &credential.token, "10.0.0.5"), Err(ApiError::Forbidden(_))));
+        assert!(matches!(authenticate_from(&state, &credential.token, "10.0.0.6"), Err(ApiError::Forbidden(_))));
+    }
+}
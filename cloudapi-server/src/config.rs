@@ -0,0 +1,65 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::identity::VmResolution;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub bind_address: String,
+    /// Address of the admin listener that serves `/metrics`, kept off the address agents
+    /// reach so metrics are not exposed to every VM. `None` disables it.
+    pub metrics_bind_address: Option<String>,
+    /// Directory of resource manifests (`*.json`) loaded at startup.
+    pub resource_dir: String,
+    pub credential_ttl_secs: u64,
+    /// When set, agents must present this secret to register. Not used in `registration`
+    /// mode, where each VirtualMachine has its own secret.
+    pub registration_secret: Option<String>,
+    /// How callers are mapped to VirtualMachine resources by source address.
+    pub vm_resolution: VmResolution,
+    /// Whether metadata requests must carry a bearer credential in addition to coming
+    /// from a known address.
+    pub require_credentials: bool,
+    /// Agents that have not sent a heartbeat for this long are marked NotReady.
+    pub heartbeat_timeout_secs: u64,
+    /// Directory of the extension package repository served alongside this instance.
+    /// When set, it must be readable for the server to report ready and its size is
+    /// reported in metrics.
+    pub package_dir: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_address: "0.0.0.0:8080".to_string(),
+            metrics_bind_address: Some("127.0.0.1:9090".to_string()),
+            resource_dir: "deployment".to_string(),
+            credential_ttl_secs: 3600,
+            registration_secret: None,
+            vm_resolution: VmResolution::default(),
+            require_credentials: true,
+            heartbeat_timeout_secs: 120,
+            package_dir: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /**
+     * Loads the server config, falling back to defaults when the file does not exist.
+     */
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            tracing::warn!("Server config not found at {}, using defaults.", path.to_string_lossy());
+            return Ok(ServerConfig::default());
+        }
+
+        let contents = std::fs::read_to_string(path)
+            .context(format!("Failed to read server config: {}", path.to_string_lossy()))?;
+
+        serde_json::from_str(&contents)
+            .context(format!("Failed to parse server config: {}", path.to_string_lossy()))
+    }
+}
@@ -0,0 +1,9 @@
+pub const SERVER_CONFIG_ENV: &str = "CLOUDAPI_SERVER_CONFIG";
+
+pub const DEFAULT_SERVER_CONFIG_FILE: &str = "server.config.json";
+
+pub const VIRTUAL_MACHINE_KIND: &str = "VirtualMachine";
+
+pub const SCHEDULED_EVENT_KIND: &str = "ScheduledEvent";
+
+pub const PENDING_WORK_HEADER: &str = cloudapi_sdk::client::PENDING_WORK_HEADER;
@@ -0,0 +1,26 @@
+use actix_web::{rt, web};
+use std::time::Duration;
+
+use crate::state::AppState;
+
+/**
+ * Periodically marks agents that missed their heartbeats as NotReady. The check runs
+ * several times per timeout so an agent is flagged shortly after its deadline passes.
+ */
+pub fn spawn_agent_health_monitor(state: web::Data<AppState>) {
+    let timeout_secs = state.config.heartbeat_timeout_secs.max(1);
+    let check_interval = Duration::from_secs((timeout_secs / 4).max(1));
+    let timeout = chrono::Duration::seconds(timeout_secs as i64);
+
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(check_interval);
+
+        loop {
+            interval.tick().await;
+
+            for resource in state.store.mark_stale_agents(timeout) {
+                tracing::warn!("Agent on {} missed its heartbeats for {}s, marking NotReady", resource, timeout_secs);
+            }
+        }
+    });
+}
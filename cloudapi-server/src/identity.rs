@@ -0,0 +1,271 @@
+use actix_web::{dev::Payload, web, FromRequest, HttpMessage, HttpRequest};
+use cloudapi_sdk::model::resource::ResourceRef;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::net::IpAddr;
+use std::sync::RwLock;
+
+use crate::api::error::ApiError;
+use crate::auth;
+use crate::middleware::metadata_guard::CallerAddress;
+use crate::resource::virtual_machine::VirtualMachine;
+use crate::resource::ResourceStore;
+use crate::state::AppState;
+
+/**
+ * How a caller's source address is mapped to a VirtualMachine.
+ */
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum VmResolution {
+    /// The VirtualMachine's name, or an address on one of its network interfaces, is the
+    /// caller's IP address.
+    #[default]
+    Address,
+    /// The VirtualMachine's name, uid or a network interface has the caller's MAC address
+    /// (e.g. `00-15-5d-01-02-03`), looked up in the host's neighbor table.
+    MacAddress,
+    /// The caller's address is bound to the VirtualMachine it names when it registers
+    /// with that VirtualMachine's own registration secret.
+    Registration,
+}
+
+pub struct VmResolver {
+    mode: VmResolution,
+    bindings: RwLock<HashMap<IpAddr, ResourceRef>>,
+}
+
+impl VmResolver {
+    pub fn new(mode: VmResolution) -> Self {
+        VmResolver {
+            mode,
+            bindings: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn mode(&self) -> VmResolution {
+        self.mode
+    }
+
+    pub fn resolve(&self, store: &ResourceStore, address: IpAddr) -> Option<VirtualMachine> {
+        let address = address.to_canonical();
+
+        match self.mode {
+            VmResolution::Address => {
+                store.find_virtual_machine(|vm| vm.has_address(address))
+            }
+            VmResolution::MacAddress => {
+                let mac = lookup_mac_address(address)?;
+                store.find_virtual_machine(|vm| {
+                    normalize_mac(&vm.metadata.name) == mac
+                        || vm.metadata.uid.as_deref().map(normalize_mac) == Some(mac.clone())
+                        || vm.mac_addresses().any(|vm_mac| normalize_mac(vm_mac) == mac)
+                })
+            }
+            VmResolution::Registration => {
+                let resource = self.bindings.read().unwrap().get(&address).cloned()?;
+                store.get_virtual_machine(&resource)
+            }
+        }
+    }
+
+    /**
+     * Binds the caller's address to a VirtualMachine. A VirtualMachine already bound to a
+     * different address is not rebound, so one guest cannot take over another's identity.
+     */
+    pub fn bind(&self, address: IpAddr, resource: ResourceRef) -> Result<(), ApiError> {
+        let address = address.to_canonical();
+        let mut bindings = self.bindings.write().unwrap();
+
+        if let Some((bound, _)) = bindings.iter().find(|(bound, bound_resource)| **bound != address && **bound_resource == resource) {
+            return Err(ApiError::Conflict(format!("VirtualMachine {} is already registered from {}", resource, bound)));
+        }
+
+        bindings.insert(address, resource);
+        Ok(())
+    }
+}
+
+fn normalize_mac(mac: &str) -> String {
+    mac.trim().to_ascii_lowercase().replace(':', "-")
+}
+
+/**
+ * Looks up the MAC address of a neighbor in the host's ARP table. Only IPv4 neighbors
+ * on Linux are supported.
+ */
+fn lookup_mac_address(address: IpAddr) -> Option<String> {
+    let arp_table = std::fs::read_to_string("/proc/net/arp").ok()?;
+
+    arp_table.lines()
+        .skip(1)
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .find(|columns| columns.first().and_then(|ip| ip.parse::<IpAddr>().ok()) == Some(address))
+        .and_then(|columns| columns.get(3).map(|mac| normalize_mac(mac)))
+}
+
+/**
+ * The VirtualMachine making a metadata request, resolved from its source address. When
+ * the request carries a credential it must belong to the same VirtualMachine.
+ */
+#[derive(Debug, Clone)]
+pub struct CallingVm {
+    pub vm: VirtualMachine,
+}
+
+impl FromRequest for CallingVm {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(resolve_calling_vm(req))
+    }
+}
+
+fn resolve_calling_vm(req: &HttpRequest) -> Result<CallingVm, ApiError> {
+    let state = req.app_data::<web::Data<AppState>>()
+        .ok_or_else(|| ApiError::Internal("Application state not configured".to_string()))?;
+
+    let caller = req.extensions().get::<CallerAddress>().copied()
+        .ok_or_else(|| ApiError::Internal("Caller address not recorded".to_string()))?;
+
+    let credential = match auth::bearer_token(req) {
+        Some(token) => Some(state.tokens.validate(token)
+            .ok_or_else(|| ApiError::Unauthorized("Invalid or expired bearer token".to_string()))?),
+        None if state.config.require_credentials => {
+            return Err(ApiError::Unauthorized("Missing bearer token".to_string()));
+        }
+        None => None,
+    };
+
+    let vm = state.resolver.resolve(&state.store, caller.0)
+        .ok_or_else(|| ApiError::NotFound(format!("No VirtualMachine is registered for caller address {}", caller.0)))?;
+
+    if let Some(credential) = &credential {
+        if credential.resource != vm.resource_ref() {
+            return Err(ApiError::Forbidden(format!("Credential for {} cannot be used from {}", credential.resource, caller.0)));
+        }
+    }
+
+    Ok(CallingVm { vm })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::TokenStore;
+    use crate::config::ServerConfig;
+    use crate::middleware::metadata_guard::{MetadataGuard, METADATA_HEADER};
+    use actix_web::test::{call_service, init_service, read_body, read_body_json, TestRequest};
+    use actix_web::{http::StatusCode, App, HttpResponse};
+    use serde_json::json;
+
+    fn store() -> ResourceStore {
+        let store = ResourceStore::default();
+
+        store.put_virtual_machine(serde_json::from_value(json!({
+            "apiVersion": "api.cloud-api.dev/v1alpha1",
+            "kind": "VirtualMachine",
+            "metadata": { "name": "vm-1", "namespace": "default" },
+            "networkInterfaces": [{ "ipAddresses": [{ "address": "10.0.0.4" }] }]
+        })).unwrap());
+
+        store.put_virtual_machine(serde_json::from_value(json!({
+            "apiVersion": "api.cloud-api.dev/v1alpha1",
+            "kind": "VirtualMachine",
+            "metadata": { "name": "10.0.0.5", "namespace": "default" }
+        })).unwrap());
+
+        store
+    }
+
+    fn address(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn resolves_by_interface_address_or_name() {
+        let resolver = VmResolver::new(VmResolution::Address);
+        let store = store();
+
+        assert_eq!(resolver.resolve(&store, address("10.0.0.4")).unwrap().metadata.name, "vm-1");
+        assert_eq!(resolver.resolve(&store, address("10.0.0.5")).unwrap().metadata.name, "10.0.0.5");
+        assert_eq!(resolver.resolve(&store, address("::ffff:10.0.0.4")).unwrap().metadata.name, "vm-1");
+        assert!(resolver.resolve(&store, address("10.0.0.6")).is_none());
+    }
+
+    #[test]
+    fn resolves_only_bound_addresses_in_registration_mode() {
+        let resolver = VmResolver::new(VmResolution::Registration);
+        let store = store();
+
+        assert!(resolver.resolve(&store, address("10.0.0.4")).is_none());
+
+        resolver.bind(address("10.0.0.9"), ResourceRef::new("default", "vm-1")).unwrap();
+        assert_eq!(resolver.resolve(&store, address("10.0.0.9")).unwrap().metadata.name, "vm-1");
+    }
+
+    #[test]
+    fn rejects_binding_a_vm_bound_to_another_address() {
+        let resolver = VmResolver::new(VmResolution::Registration);
+        let store = store();
+        let vm = ResourceRef::new("default", "vm-1");
+
+        resolver.bind(address("10.0.0.9"), vm.clone()).unwrap();
+        resolver.bind(address("10.0.0.9"), vm.clone()).unwrap();
+
+        assert!(matches!(resolver.bind(address("10.0.0.10"), vm), Err(ApiError::Conflict(_))));
+        assert!(resolver.resolve(&store, address("10.0.0.10")).is_none());
+        assert_eq!(resolver.resolve(&store, address("10.0.0.9")).unwrap().metadata.name, "vm-1");
+    }
+
+    async fn calling_vm(vm: CallingVm) -> HttpResponse {
+        HttpResponse::Ok().body(vm.vm.metadata.name)
+    }
+
+    macro_rules! metadata_app {
+        () => {
+            init_service(
+                App::new()
+                    .app_data(web::Data::new(AppState {
+                        config: ServerConfig { require_credentials: false, ..ServerConfig::default() },
+                        store: store(),
+                        tokens: TokenStore::new(3600),
+                        resolver: VmResolver::new(VmResolution::Address),
+                        metrics: Default::default(),
+                    }))
+                    .wrap(MetadataGuard)
+                    .route("/", web::get().to(calling_vm))
+            ).await
+        };
+    }
+
+    #[actix_web::test]
+    async fn resolves_calling_vm_from_source_address() {
+        let app = metadata_app!();
+        let req = TestRequest::get().uri("/")
+            .insert_header((METADATA_HEADER, "true"))
+            .peer_addr("10.0.0.4:50000".parse().unwrap())
+            .to_request();
+
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(read_body(res).await, "vm-1");
+    }
+
+    #[actix_web::test]
+    async fn rejects_unknown_caller_with_not_found() {
+        let app = metadata_app!();
+        let req = TestRequest::get().uri("/")
+            .insert_header((METADATA_HEADER, "true"))
+            .peer_addr("10.0.0.6:50000".parse().unwrap())
+            .to_request();
+
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let body: serde_json::Value = read_body_json(res).await;
+        assert_eq!(body["error"], "No VirtualMachine is registered for caller address 10.0.0.6");
+    }
+}
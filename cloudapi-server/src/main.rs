@@ -1,10 +1,73 @@
+mod api;
+mod auth;
+mod config;
+mod constants;
+mod health;
+mod identity;
+mod metrics;
+mod middleware;
+mod resource;
+mod state;
+
+use actix_web::{web, App, HttpServer};
 use anyhow::Result;
+use std::path::Path;
+
+use config::ServerConfig;
+use middleware::request_metrics::RequestMetrics;
+use resource::ResourceStore;
+use state::AppState;
 
-#[tokio::main]
+#[actix_web::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
 
-    tracing::info!("Starting cloudapi-agent service...");
+    tracing::info!("Starting cloudapi-server...");
+
+    let config_file = std::env::var(constants::SERVER_CONFIG_ENV)
+        .unwrap_or_else(|_| constants::DEFAULT_SERVER_CONFIG_FILE.to_string());
+    let config = ServerConfig::load(Path::new(&config_file))?;
+
+    let state = web::Data::new(AppState {
+        store: ResourceStore::load_dir(Path::new(&config.resource_dir))?,
+        tokens: auth::TokenStore::new(config.credential_ttl_secs),
+        resolver: identity::VmResolver::new(config.vm_resolution),
+        metrics: Default::default(),
+        config: config.clone(),
+    });
+
+    health::spawn_agent_health_monitor(state.clone());
+
+    tracing::info!("Listening on {}", config.bind_address);
+
+    let api_state = state.clone();
+    let api = HttpServer::new(move || {
+        App::new()
+            .wrap(RequestMetrics::new(api_state.metrics.clone()))
+            .app_data(api_state.clone())
+            .configure(api::configure)
+    })
+    .bind(&config.bind_address)?
+    .run();
+
+    let Some(metrics_bind_address) = &config.metrics_bind_address else {
+        api.await?;
+        return Ok(());
+    };
+
+    tracing::info!("Serving metrics on {}", metrics_bind_address);
+
+    let admin = HttpServer::new(move || {
+        App::new()
+            .wrap(RequestMetrics::new(state.metrics.clone()))
+            .app_data(state.clone())
+            .configure(api::configure_admin)
+    })
+    .workers(1)
+    .bind(metrics_bind_address)?
+    .run();
+
+    futures_util::future::try_join(api, admin).await?;
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,158 @@
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::metrics::histogram::{exponential_buckets, Histogram};
+use prometheus_client::registry::{Registry, Unit};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::constants;
+use crate::resource::ResourceStore;
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct RequestLabels {
+    method: String,
+    route: String,
+    status: u16,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct RouteLabels {
+    method: String,
+    route: String,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct KindLabels {
+    kind: String,
+}
+
+pub struct InFlightGuard(Gauge);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+type HistogramFamily<L> = Family<L, Histogram, fn() -> Histogram>;
+
+/**
+ * Server metrics in the Prometheus text format. Request metrics are recorded by the
+ * `RequestMetrics` middleware; resource and package repository gauges are refreshed when
+ * `/metrics` is scraped on the admin listener. There is no active watches gauge since the
+ * server has no watch API; agents poll, which the request metrics already cover.
+ */
+pub struct ServerMetrics {
+    registry: Registry,
+    requests: Family<RequestLabels, Counter>,
+    request_duration: HistogramFamily<RouteLabels>,
+    requests_in_flight: Gauge,
+    resources: Family<KindLabels, Gauge>,
+    package_repository_packages: Gauge,
+    package_repository_bytes: Gauge,
+}
+
+impl Default for ServerMetrics {
+    fn default() -> Self {
+        let mut registry = Registry::with_prefix("cloudapi_server");
+
+        let requests = Family::<RequestLabels, Counter>::default();
+        registry.register("http_requests", "HTTP requests by method, route and status", requests.clone());
+
+        let request_duration: HistogramFamily<RouteLabels> = Family::new_with_constructor(|| Histogram::new(exponential_buckets(0.001, 2.0, 14)));
+        registry.register_with_unit("http_request_duration", "HTTP request latency by method and route", Unit::Seconds, request_duration.clone());
+
+        let requests_in_flight = Gauge::default();
+        registry.register("http_requests_in_flight", "HTTP requests currently being served", requests_in_flight.clone());
+
+        let resources = Family::<KindLabels, Gauge>::default();
+        registry.register("resources", "Resources held by this instance by kind", resources.clone());
+
+        let package_repository_packages = Gauge::default();
+        registry.register("package_repository_packages", "Extension packages in the package repository", package_repository_packages.clone());
+
+        let package_repository_bytes = Gauge::default();
+        registry.register_with_unit("package_repository", "Size of the package repository", Unit::Bytes, package_repository_bytes.clone());
+
+        ServerMetrics {
+            registry,
+            requests,
+            request_duration,
+            requests_in_flight,
+            resources,
+            package_repository_packages,
+            package_repository_bytes,
+        }
+    }
+}
+
+impl ServerMetrics {
+    /**
+     * Counts a request as in flight until the returned guard is dropped, which also covers
+     * requests abandoned when the client disconnects.
+     */
+    pub fn track_in_flight(&self) -> InFlightGuard {
+        self.requests_in_flight.inc();
+        InFlightGuard(self.requests_in_flight.clone())
+    }
+
+    pub fn request_finished(&self, method: &str, route: &str, status: u16, duration: Duration) {
+        self.requests.get_or_create(&RequestLabels {
+            method: method.to_string(),
+            route: route.to_string(),
+            status,
+        }).inc();
+
+        self.request_duration.get_or_create(&RouteLabels {
+            method: method.to_string(),
+            route: route.to_string(),
+        }).observe(duration.as_secs_f64());
+    }
+
+    pub fn set_package_repository_size(&self, packages: u64, bytes: u64) {
+        self.package_repository_packages.set(packages as i64);
+        self.package_repository_bytes.set(bytes as i64);
+    }
+
+    /**
+     * Refreshes the gauges that describe stored state, then encodes every metric.
+     */
+    pub fn encode(&self, store: &ResourceStore) -> Result<String, std::fmt::Error> {
+        let (virtual_machines, scheduled_events) = store.counts();
+        self.resources.get_or_create(&KindLabels { kind: constants::VIRTUAL_MACHINE_KIND.to_string() }).set(virtual_machines as i64);
+        self.resources.get_or_create(&KindLabels { kind: constants::SCHEDULED_EVENT_KIND.to_string() }).set(scheduled_events as i64);
+
+        let mut buffer = String::new();
+        prometheus_client::encoding::text::encode(&mut buffer, &self.registry)?;
+        Ok(buffer)
+    }
+}
+
+/**
+ * Counts the `.extpkg` files under `dir` and the total size of all files in it.
+ */
+pub fn package_repository_size(dir: &Path) -> std::io::Result<(u64, u64)> {
+    let mut packages = 0;
+    let mut bytes = 0;
+
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+
+        if metadata.is_dir() {
+            let (nested_packages, nested_bytes) = package_repository_size(&entry.path())?;
+            packages += nested_packages;
+            bytes += nested_bytes;
+        } else {
+            if entry.path().extension().and_then(|ext| ext.to_str()) == Some("extpkg") {
+                packages += 1;
+            }
+
+            bytes += metadata.len();
+        }
+    }
+
+    Ok((packages, bytes))
+}
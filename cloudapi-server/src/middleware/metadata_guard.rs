@@ -0,0 +1,187 @@
+use actix_service::{Service, Transform};
+use actix_web::body::EitherBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest, ResponseError};
+use futures_util::future::{ok, ready, LocalBoxFuture, Ready};
+use std::net::IpAddr;
+use std::rc::Rc;
+
+use crate::api::error::ApiError;
+
+pub const METADATA_HEADER: &str = "Metadata";
+
+pub const FORWARDED_FOR_HEADER: &str = "X-Forwarded-For";
+
+/**
+ * The source address of the calling VM, recorded by `MetadataGuard`.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallerAddress(pub IpAddr);
+
+impl FromRequest for CallerAddress {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<CallerAddress>()
+                .copied()
+                .ok_or_else(|| ApiError::Internal("Caller address not recorded".to_string()))
+        )
+    }
+}
+
+/**
+ * Mirrors cloud IMDS SSRF protection: requests must carry `Metadata: true`, must not
+ * carry `X-Forwarded-For` (so they cannot have been relayed by a proxy on the VM), and
+ * are attributed to the VM at their source address.
+ */
+pub struct MetadataGuard;
+
+impl<S, B> Transform<S, ServiceRequest> for MetadataGuard
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = MetadataGuardMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(MetadataGuardMiddleware { service: Rc::new(service) })
+    }
+}
+
+pub struct MetadataGuardMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for MetadataGuardMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_service::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if let Err(e) = check_request(&req) {
+            tracing::warn!("Rejected {} {} from {:?}: {}", req.method(), req.path(), req.peer_addr(), e);
+            let response = req.into_response(e.error_response()).map_into_right_body();
+            return Box::pin(ok(response));
+        }
+
+        if let Some(peer_addr) = req.peer_addr() {
+            req.extensions_mut().insert(CallerAddress(peer_addr.ip()));
+        }
+
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            let res = service.call(req).await?;
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+fn check_request(req: &ServiceRequest) -> Result<(), ApiError> {
+    if req.headers().contains_key(FORWARDED_FOR_HEADER) {
+        return Err(ApiError::Forbidden("Requests carrying X-Forwarded-For are not allowed".to_string()));
+    }
+
+    let has_metadata_header = req.headers()
+        .get(METADATA_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+
+    if !has_metadata_header {
+        return Err(ApiError::BadRequest("Required metadata header not specified".to_string()));
+    }
+
+    if req.peer_addr().is_none() {
+        return Err(ApiError::BadRequest("Unable to determine caller address".to_string()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, web, App, HttpResponse};
+
+    async fn caller(address: CallerAddress) -> HttpResponse {
+        HttpResponse::Ok().body(address.0.to_string())
+    }
+
+    macro_rules! guarded_app {
+        () => {
+            test::init_service(App::new().wrap(MetadataGuard).route("/", web::get().to(caller))).await
+        };
+    }
+
+    #[actix_web::test]
+    async fn rejects_missing_metadata_header() {
+        let app = guarded_app!();
+        let req = test::TestRequest::get().uri("/").peer_addr("10.0.0.4:50000".parse().unwrap()).to_request();
+
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn rejects_metadata_header_not_true() {
+        let app = guarded_app!();
+        let req = test::TestRequest::get().uri("/")
+            .insert_header((METADATA_HEADER, "false"))
+            .peer_addr("10.0.0.4:50000".parse().unwrap())
+            .to_request();
+
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn rejects_forwarded_requests() {
+        let app = guarded_app!();
+        let req = test::TestRequest::get().uri("/")
+            .insert_header((METADATA_HEADER, "true"))
+            .insert_header((FORWARDED_FOR_HEADER, "10.0.0.5"))
+            .peer_addr("10.0.0.4:50000".parse().unwrap())
+            .to_request();
+
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn rejects_unknown_source_address() {
+        let app = guarded_app!();
+        let req = test::TestRequest::get().uri("/").insert_header((METADATA_HEADER, "true")).to_request();
+
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn records_caller_address() {
+        let app = guarded_app!();
+        let req = test::TestRequest::get().uri("/")
+            .insert_header((METADATA_HEADER, "True"))
+            .peer_addr("10.0.0.4:50000".parse().unwrap())
+            .to_request();
+
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(test::read_body(res).await, "10.0.0.4");
+    }
+}
@@ -0,0 +1,2 @@
+pub mod metadata_guard;
+pub mod request_metrics;
@@ -0,0 +1,201 @@
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::Error;
+use futures_util::future::{ok, LocalBoxFuture, Ready};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::metrics::ServerMetrics;
+
+/**
+ * Label used for requests that matched no route, so that probing random paths cannot
+ * create unbounded label values.
+ */
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+/**
+ * Label used for request methods outside the standard set, for the same reason.
+ */
+pub const OTHER_METHOD: &str = "other";
+
+/**
+ * Records the count and latency of every request, labelled by the route pattern it
+ * matched (e.g. `/cloud-api/v1/namespaces/{namespace}/virtualmachines/{name}/status`)
+ * rather than the concrete path.
+ */
+pub struct RequestMetrics {
+    metrics: Arc<ServerMetrics>,
+}
+
+impl RequestMetrics {
+    pub fn new(metrics: Arc<ServerMetrics>) -> Self {
+        RequestMetrics { metrics }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestMetricsMiddleware {
+            service: Rc::new(service),
+            metrics: self.metrics.clone(),
+        })
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: Rc<S>,
+    metrics: Arc<ServerMetrics>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_service::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let method = method_label(req.method());
+        let route = req.match_pattern().unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+
+        let service = Rc::clone(&self.service);
+        let metrics = self.metrics.clone();
+
+        Box::pin(async move {
+            let _in_flight = metrics.track_in_flight();
+            let res = service.call(req).await;
+
+            let status = match &res {
+                Ok(res) => res.status(),
+                Err(e) => e.as_response_error().status_code(),
+            };
+
+            metrics.request_finished(method, &route, status.as_u16(), started.elapsed());
+            res
+        })
+    }
+}
+
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::HEAD => "HEAD",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        Method::PATCH => "PATCH",
+        Method::OPTIONS => "OPTIONS",
+        Method::CONNECT => "CONNECT",
+        Method::TRACE => "TRACE",
+        _ => OTHER_METHOD,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource::ResourceStore;
+    use actix_web::{http::StatusCode, test, web, App, HttpResponse};
+
+    async fn ok_handler() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    async fn not_found_handler() -> HttpResponse {
+        HttpResponse::NotFound().finish()
+    }
+
+    macro_rules! metered_app {
+        ($metrics:expr) => {
+            test::init_service(
+                App::new()
+                    .wrap(RequestMetrics::new($metrics.clone()))
+                    .route("/items/{name}", web::get().to(ok_handler))
+                    .route("/missing", web::get().to(not_found_handler))
+            ).await
+        };
+    }
+
+    fn encoded(metrics: &ServerMetrics) -> String {
+        metrics.encode(&ResourceStore::default()).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn labels_requests_by_route_pattern() {
+        let metrics = Arc::new(ServerMetrics::default());
+        let app = metered_app!(metrics);
+
+        for name in ["a", "b"] {
+            let req = test::TestRequest::get().uri(&format!("/items/{}", name)).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        }
+
+        let output = encoded(&metrics);
+        assert!(output.contains(r#"cloudapi_server_http_requests_total{method="GET",route="/items/{name}",status="200"} 2"#), "{}", output);
+        assert!(output.contains(r#"cloudapi_server_http_request_duration_seconds_count{method="GET",route="/items/{name}"} 2"#), "{}", output);
+    }
+
+    #[actix_web::test]
+    async fn records_response_status() {
+        let metrics = Arc::new(ServerMetrics::default());
+        let app = metered_app!(metrics);
+
+        let req = test::TestRequest::get().uri("/missing").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+
+        let output = encoded(&metrics);
+        assert!(output.contains(r#"cloudapi_server_http_requests_total{method="GET",route="/missing",status="404"} 1"#), "{}", output);
+    }
+
+    #[actix_web::test]
+    async fn collapses_unmatched_paths() {
+        let metrics = Arc::new(ServerMetrics::default());
+        let app = metered_app!(metrics);
+
+        for path in ["/nope", "/also/nope"] {
+            let req = test::TestRequest::get().uri(path).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+        }
+
+        let output = encoded(&metrics);
+        assert!(output.contains(r#"cloudapi_server_http_requests_total{method="GET",route="unmatched",status="404"} 2"#), "{}", output);
+        assert!(output.contains("cloudapi_server_http_requests_in_flight 0"), "{}", output);
+    }
+
+    #[actix_web::test]
+    async fn collapses_non_standard_methods() {
+        let metrics = Arc::new(ServerMetrics::default());
+        let app = metered_app!(metrics);
+
+        for method in ["PROPFIND", "X-PROBE-1"] {
+            let req = test::TestRequest::default()
+                .method(Method::from_bytes(method.as_bytes()).unwrap())
+                .uri("/items/a")
+                .to_request();
+            test::call_service(&app, req).await;
+        }
+
+        let output = encoded(&metrics);
+        assert!(output.contains(r#"cloudapi_server_http_requests_total{method="other",route="/items/{name}",status="404"} 2"#), "{}", output);
+        assert!(!output.contains("PROPFIND"), "{}", output);
+    }
+}
@@ -0,0 +1,183 @@
+pub mod scheduled_event;
+pub mod virtual_machine;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use cloudapi_sdk::model::heartbeat::{AgentHeartbeat, AgentState, AgentStatus};
+use cloudapi_sdk::model::resource::ResourceRef;
+use cloudapi_sdk::model::status::VirtualMachineStatus;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::RwLock;
+
+use crate::constants;
+use scheduled_event::ScheduledEventResource;
+use virtual_machine::VirtualMachine;
+
+/**
+ * In-memory store of the resources served by this instance, seeded from the manifests
+ * in the resource directory.
+ */
+#[derive(Default)]
+pub struct ResourceStore {
+    virtual_machines: RwLock<HashMap<ResourceRef, VirtualMachine>>,
+    scheduled_events: RwLock<HashMap<ResourceRef, ScheduledEventResource>>,
+}
+
+impl ResourceStore {
+    pub fn load_dir(dir: &Path) -> Result<Self> {
+        let store = ResourceStore::default();
+
+        let entries = std::fs::read_dir(dir)
+            .context(format!("Failed to read resource directory: {}", dir.to_string_lossy()))?;
+
+        for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+
+            let contents = std::fs::read_to_string(&path)
+                .context(format!("Failed to read resource: {}", path.to_string_lossy()))?;
+            let manifest: serde_json::Value = serde_json::from_str(&contents)
+                .context(format!("Failed to parse resource: {}", path.to_string_lossy()))?;
+
+            match manifest.get("kind").and_then(|kind| kind.as_str()) {
+                Some(constants::VIRTUAL_MACHINE_KIND) => {
+                    let vm: VirtualMachine = serde_json::from_value(manifest)
+                        .context(format!("Failed to parse VirtualMachine: {}", path.to_string_lossy()))?;
+
+                    tracing::info!("Loaded VirtualMachine {} from {}", vm.resource_ref(), path.to_string_lossy());
+                    store.put_virtual_machine(vm);
+                }
+                Some(constants::SCHEDULED_EVENT_KIND) => {
+                    let event: ScheduledEventResource = serde_json::from_value(manifest)
+                        .context(format!("Failed to parse ScheduledEvent: {}", path.to_string_lossy()))?;
+
+                    tracing::info!("Loaded ScheduledEvent {} from {}", event.resource_ref(), path.to_string_lossy());
+                    store.put_scheduled_event(event);
+                }
+                _ => {
+                    tracing::debug!("Skipping unsupported resource: {}", path.to_string_lossy());
+                }
+            }
+        }
+
+        Ok(store)
+    }
+
+    /**
+     * The number of VirtualMachine and ScheduledEvent resources held.
+     */
+    pub fn counts(&self) -> (usize, usize) {
+        (self.virtual_machines.read().unwrap().len(), self.scheduled_events.read().unwrap().len())
+    }
+
+    /**
+     * Whether the store can still serve requests. A handler that panicked while holding a
+     * write lock leaves the store poisoned, and every later access would panic too.
+     */
+    pub fn is_available(&self) -> bool {
+        !self.virtual_machines.is_poisoned() && !self.scheduled_events.is_poisoned()
+    }
+
+    pub fn get_virtual_machine(&self, resource: &ResourceRef) -> Option<VirtualMachine> {
+        self.virtual_machines.read().unwrap().get(resource).cloned()
+    }
+
+    pub fn find_virtual_machine<F>(&self, predicate: F) -> Option<VirtualMachine>
+    where
+        F: Fn(&VirtualMachine) -> bool,
+    {
+        self.virtual_machines.read().unwrap().values().find(|vm| predicate(vm)).cloned()
+    }
+
+    pub fn put_virtual_machine(&self, vm: VirtualMachine) {
+        self.virtual_machines.write().unwrap().insert(vm.resource_ref(), vm);
+    }
+
+    /**
+     * Replaces the reported status of a VM. The agent health recorded from heartbeats is
+     * kept unless the new status carries its own.
+     */
+    pub fn set_virtual_machine_status(&self, resource: &ResourceRef, mut status: VirtualMachineStatus) -> bool {
+        match self.virtual_machines.write().unwrap().get_mut(resource) {
+            Some(vm) => {
+                if status.agent.is_none() {
+                    status.agent = vm.status.take().and_then(|previous| previous.agent);
+                }
+
+                vm.status = Some(status);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn record_heartbeat(&self, resource: &ResourceRef, heartbeat: AgentHeartbeat) -> bool {
+        match self.virtual_machines.write().unwrap().get_mut(resource) {
+            Some(vm) => {
+                vm.status.get_or_insert_with(VirtualMachineStatus::default).agent = Some(AgentStatus {
+                    state: AgentState::Ready,
+                    last_heartbeat_at: Utc::now(),
+                    heartbeat,
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /**
+     * Marks every Ready agent whose last heartbeat is older than `timeout` as NotReady,
+     * returning the affected VMs.
+     */
+    pub fn mark_stale_agents(&self, timeout: chrono::Duration) -> Vec<ResourceRef> {
+        let cutoff = Utc::now() - timeout;
+        let mut stale = Vec::new();
+
+        for vm in self.virtual_machines.write().unwrap().values_mut() {
+            let Some(agent) = vm.status.as_mut().and_then(|status| status.agent.as_mut()) else {
+                continue;
+            };
+
+            if agent.state == AgentState::Ready && agent.last_heartbeat_at < cutoff {
+                agent.state = AgentState::NotReady;
+                stale.push(vm.resource_ref());
+            }
+        }
+
+        stale
+    }
+
+    pub fn put_scheduled_event(&self, event: ScheduledEventResource) {
+        self.scheduled_events.write().unwrap().insert(event.resource_ref(), event);
+    }
+
+    pub fn list_scheduled_events(&self, vm: &ResourceRef) -> Vec<ScheduledEventResource> {
+        let mut events: Vec<ScheduledEventResource> = self.scheduled_events.read().unwrap()
+            .values()
+            .filter(|event| event.target() == *vm)
+            .cloned()
+            .collect();
+
+        events.sort_by_key(|event| event.spec.not_before);
+        events
+    }
+
+    /**
+     * Marks the VM's events with the given ids as acknowledged, returning how many were found.
+     */
+    pub fn acknowledge_scheduled_events(&self, vm: &ResourceRef, event_ids: &[String]) -> usize {
+        let now = Utc::now();
+        let mut acknowledged = 0;
+
+        for event in self.scheduled_events.write().unwrap().values_mut() {
+            if event.target() == *vm && event_ids.contains(&event.to_event().event_id) {
+                event.status.acknowledged_at.get_or_insert(now);
+                acknowledged += 1;
+            }
+        }
+
+        acknowledged
+    }
+}
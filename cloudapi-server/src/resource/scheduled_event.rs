@@ -0,0 +1,58 @@
+use chrono::{DateTime, Utc};
+use cloudapi_sdk::model::events::{ScheduledEvent, ScheduledEventType};
+use cloudapi_sdk::model::resource::ResourceRef;
+use serde::{Deserialize, Serialize};
+
+use super::virtual_machine::ObjectMeta;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduledEventSpec {
+    /// Name of the targeted VirtualMachine in the event's namespace.
+    pub virtual_machine: String,
+    pub event_type: ScheduledEventType,
+    pub not_before: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduledEventStatus {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acknowledged_at: Option<DateTime<Utc>>,
+}
+
+/**
+ * Maintenance planned by the platform for a single VirtualMachine.
+ */
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduledEventResource {
+    pub api_version: String,
+    pub kind: String,
+    pub metadata: ObjectMeta,
+    pub spec: ScheduledEventSpec,
+    #[serde(default)]
+    pub status: ScheduledEventStatus,
+}
+
+impl ScheduledEventResource {
+    pub fn resource_ref(&self) -> ResourceRef {
+        ResourceRef::new(&self.metadata.namespace, &self.metadata.name)
+    }
+
+    pub fn target(&self) -> ResourceRef {
+        ResourceRef::new(&self.metadata.namespace, &self.spec.virtual_machine)
+    }
+
+    pub fn to_event(&self) -> ScheduledEvent {
+        ScheduledEvent {
+            event_id: self.metadata.uid.clone().unwrap_or_else(|| self.metadata.name.clone()),
+            event_type: self.spec.event_type,
+            not_before: self.spec.not_before,
+            description: self.spec.description.clone(),
+            acknowledged_at: self.status.acknowledged_at,
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use cloudapi_sdk::model::compute::{Disk, ImageReference, MetadataResponse, NetworkInterface};
+use cloudapi_sdk::model::extension::ExtensionState;
+use cloudapi_sdk::model::resource::ResourceRef;
+use cloudapi_sdk::model::status::VirtualMachineStatus;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ObjectMeta {
+    pub name: String,
+    pub namespace: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VirtualMachine {
+    pub api_version: String,
+    pub kind: String,
+    pub metadata: ObjectMeta,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vm_size: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub network_interfaces: Vec<NetworkInterface>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_reference: Option<ImageReference>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disks: Vec<Disk>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_data: Option<String>,
+    /// Hex SHA-256 of the secret an agent must present to register as this VM when
+    /// callers are resolved by registration.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registration_secret_sha256: Option<String>,
+    #[serde(default)]
+    pub extensions: Vec<ExtensionState>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<VirtualMachineStatus>,
+}
+
+impl VirtualMachine {
+    pub fn resource_ref(&self) -> ResourceRef {
+        ResourceRef::new(&self.metadata.namespace, &self.metadata.name)
+    }
+
+    pub fn to_metadata(&self) -> MetadataResponse {
+        MetadataResponse {
+            instance_id: self.metadata.uid.clone().unwrap_or_else(|| self.resource_ref().to_string()),
+            location: self.location.clone().unwrap_or_default(),
+            name: self.metadata.name.clone(),
+            os_type: self.os_type.clone().unwrap_or_default(),
+            zone: self.zone.clone(),
+            vm_size: self.vm_size.clone(),
+            tags: self.tags.clone(),
+            labels: self.metadata.labels.clone(),
+            network_interfaces: self.network_interfaces.clone(),
+            image_reference: self.image_reference.clone(),
+            disks: self.disks.clone(),
+            user_data: self.user_data.clone(),
+            additional_properties: BTreeMap::new(),
+        }
+    }
+
+    /**
+     * Whether the address belongs to this VM, either as its name or on one of its
+     * network interfaces.
+     */
+    pub fn has_address(&self, address: IpAddr) -> bool {
+        self.metadata.name.parse::<IpAddr>().ok() == Some(address)
+            || self.network_interfaces.iter()
+                .flat_map(|nic| nic.ip_addresses.iter())
+                .any(|ip| ip.address.parse::<IpAddr>().ok().map(|ip| ip.to_canonical()) == Some(address))
+    }
+
+    /**
+     * Whether `secret` is this VM's registration secret. A VM without one cannot be
+     * registered as by name.
+     */
+    pub fn has_registration_secret(&self, secret: &str) -> bool {
+        let digest = format!("{:x}", Sha256::digest(secret.as_bytes()));
+
+        self.registration_secret_sha256.as_deref()
+            .is_some_and(|expected| expected.trim().eq_ignore_ascii_case(&digest))
+    }
+
+    pub fn mac_addresses(&self) -> impl Iterator<Item = &str> {
+        self.network_interfaces.iter().filter_map(|nic| nic.mac_address.as_deref())
+    }
+
+    /**
+     * Whether an assigned extension has not yet been reported at its assigned version,
+     * meaning the agent should poll again soon.
+     */
+    pub fn has_pending_work(&self) -> bool {
+        let reported = self.status.as_ref().map(|status| status.extensions.as_slice()).unwrap_or_default();
+
+        self.extensions.iter().any(|desired| {
+            !reported.iter().any(|observed| observed.uid == desired.uid && observed.version == desired.version)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn virtual_machine(assigned: &[(&str, &str)], reported: Option<&[(&str, &str)]>) -> VirtualMachine {
+        let extensions = |extensions: &[(&str, &str)]| extensions.iter()
+            .map(|(uid, version)| ExtensionState::new(uid, "extension", version))
+            .collect::<Vec<_>>();
+
+        let mut vm: VirtualMachine = serde_json::from_value(json!({
+            "apiVersion": "api.cloud-api.dev/v1alpha1",
+            "kind": "VirtualMachine",
+            "metadata": { "name": "vm-1", "namespace": "default" }
+        })).unwrap();
+
+        vm.extensions = extensions(assigned);
+        vm.status = reported.map(|reported| VirtualMachineStatus {
+            reported_at: None,
+            extensions: extensions(reported),
+            agent: None,
+        });
+
+        vm
+    }
+
+    #[test]
+    fn work_is_pending_until_every_extension_is_reported_at_its_version() {
+        assert!(virtual_machine(&[("uid-1", "1.0.0")], None).has_pending_work());
+        assert!(virtual_machine(&[("uid-1", "1.0.0")], Some(&[("uid-1", "0.9.0")])).has_pending_work());
+        assert!(virtual_machine(&[("uid-1", "1.0.0"), ("uid-2", "1.0.0")], Some(&[("uid-1", "1.0.0")])).has_pending_work());
+
+        assert!(!virtual_machine(&[("uid-1", "1.0.0")], Some(&[("uid-1", "1.0.0"), ("uid-3", "1.0.0")])).has_pending_work());
+    }
+
+    #[test]
+    fn nothing_is_pending_without_extensions() {
+        assert!(!virtual_machine(&[], None).has_pending_work());
+    }
+}
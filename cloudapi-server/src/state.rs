@@ -0,0 +1,15 @@
+use std::sync::Arc;
+
+use crate::auth::TokenStore;
+use crate::config::ServerConfig;
+use crate::identity::VmResolver;
+use crate::metrics::ServerMetrics;
+use crate::resource::ResourceStore;
+
+pub struct AppState {
+    pub config: ServerConfig,
+    pub store: ResourceStore,
+    pub tokens: TokenStore,
+    pub resolver: VmResolver,
+    pub metrics: Arc<ServerMetrics>,
+}